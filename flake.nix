@@ -30,6 +30,8 @@
           fish
           cargo rustc rustfmt clippy rust-analyzer
           opencv libX11 libXrandr
+          # dbus-daemon for the D-Bus tests
+          dbus
         ];
         nativeBuildInputs = [ pkgs.pkg-config pkgs.clang ];
 
@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+/// First wait before retrying a lost device or camera, doubled after every failure
+const MIN_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// When to try again to open something that went away
+pub struct Backoff {
+    delay: Duration,
+    retry_at: Instant,
+}
+
+impl Backoff {
+    /// The first attempt is due right away
+    pub fn new(now: Instant) -> Self {
+        Self {
+            delay: MIN_DELAY,
+            retry_at: now,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.retry_at
+    }
+
+    /// Puts the next attempt after the current delay and doubles it, returns
+    /// the wait until then
+    pub fn retry_later(&mut self, now: Instant) -> Duration {
+        let delay = self.delay;
+        self.retry_at = now + delay;
+        self.delay = (delay * 2).min(MAX_DELAY);
+        delay
+    }
+
+    /// Goes back to the shortest delay once the device works again
+    pub fn reset(&mut self) {
+        self.delay = MIN_DELAY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let start = Instant::now();
+        let mut backoff = Backoff::new(start);
+        assert!(backoff.is_due(start));
+
+        let mut now = start;
+        let mut delays = Vec::new();
+        for _ in 0..7 {
+            let delay = backoff.retry_later(now);
+            assert!(!backoff.is_due(now + delay - Duration::from_millis(1)));
+            now += delay;
+            assert!(backoff.is_due(now));
+            delays.push(delay.as_secs());
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+
+        backoff.reset();
+        assert_eq!(backoff.retry_later(now), MIN_DELAY);
+    }
+}
@@ -0,0 +1,429 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{CurrentLimit, PerChannel};
+
+/// Gamma and white balance applied to the colors an output sends
+#[derive(Clone, Debug)]
+pub struct ColorCorrection {
+    /// Corrected value of every input value, per channel
+    lut: [[u8; 256]; 3],
+}
+
+impl ColorCorrection {
+    /// `gamma` > 1 darkens the dim values, `gain` scales each channel
+    pub fn new(gamma: PerChannel, gain: PerChannel) -> Self {
+        let gamma = gamma.rgb();
+        let gain = gain.rgb();
+        let mut lut = [[0u8; 256]; 3];
+        for (channel, table) in lut.iter_mut().enumerate() {
+            for (value, out) in table.iter_mut().enumerate() {
+                let linear = (value as f32 / 255.0).powf(gamma[channel]);
+                *out = (linear * gain[channel] * 255.0).clamp(0.0, 255.0).round() as u8;
+            }
+        }
+        Self { lut }
+    }
+
+    pub fn apply(&self, colors: &mut [[u8; 3]]) {
+        for color in colors {
+            for (value, table) in color.iter_mut().zip(&self.lut) {
+                *value = table[*value as usize];
+            }
+        }
+    }
+}
+
+/// Estimated current drawn by `colors` in mA, scaled down uniformly to stay
+/// within `limit`. The estimate before scaling is returned.
+///
+/// Called with the frame of one output: every output drives its own
+/// controller, usually on its own supply, so one strip showing white
+/// shouldn't dim another.
+pub fn limit_current(colors: &mut [[u8; 3]], limit: &CurrentLimit) -> f32 {
+    let total: u64 = colors.iter().flatten().map(|&c| c as u64).sum();
+    let draw = total as f32 / 255.0 * limit.ma_per_channel;
+    if draw > limit.max_ma {
+        let scale = limit.max_ma / draw;
+        for value in colors.iter_mut().flatten() {
+            // Rounding down keeps the result within the budget
+            *value = (*value as f32 * scale) as u8;
+        }
+    }
+    draw
+}
+
+/// Saturation, vibrance and hue changes applied to each region's color
+#[derive(Clone, Debug)]
+pub struct ColorAdjustment {
+    /// Multiplier of the saturation
+    pub saturation: f32,
+    /// Extra saturation for dull colors, -1 to 1, barely changes vivid ones
+    pub vibrance: f32,
+    /// Rotation of the hue in degrees
+    pub hue_shift: f32,
+    /// Saturation, 0 to 1, below which colors turn white instead of a tint
+    pub white_threshold: Option<f32>,
+}
+
+impl ColorAdjustment {
+    /// Adjusts `[r, g, b]` with channels from 0 to 255
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        if self.saturation == 1.0
+            && self.vibrance == 0.0
+            && self.hue_shift == 0.0
+            && self.white_threshold.is_none()
+        {
+            return rgb;
+        }
+
+        let [h, s, v] = rgb_to_hsv(rgb);
+        let s = if self.white_threshold.is_some_and(|t| s < t) {
+            0.0
+        } else {
+            let s = s * self.saturation;
+            (s + self.vibrance * s * (1.0 - s)).clamp(0.0, 1.0)
+        };
+        hsv_to_rgb([(h + self.hue_shift).rem_euclid(360.0), s, v])
+    }
+}
+
+/// Hue in degrees, saturation from 0 to 1 and value from 0 to 255
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta <= 0.0 {
+        return [0.0, 0.0, max];
+    }
+
+    let h = if max == r {
+        (g - b) / delta
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    [(h * 60.0).rem_euclid(360.0), delta / max, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let chroma = v * s;
+    let sector = h / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let m = v - chroma;
+    [r + m, g + m, b + m]
+}
+
+/// How fast the smoothed colors follow the captured ones
+#[derive(Clone, Debug)]
+pub struct Smoothing {
+    /// Time constant when a channel gets brighter
+    pub attack: Duration,
+    /// Time constant when a channel gets darker
+    pub decay: Duration,
+    /// Mean change of a monitor's colors, 0 to 1, above which it snaps
+    pub scene_cut: Option<f32>,
+}
+
+/// Exponential moving average of the combined buffer, shared by all sinks.
+///
+/// Every update moves the colors by the time passed since the previous one,
+/// so the result doesn't depend on how often or by how many sinks it runs.
+#[derive(Default)]
+pub struct Smoother {
+    current: Vec<[f32; 3]>,
+    last: Option<Instant>,
+}
+
+impl Smoother {
+    /// Moves towards `target` by the time since the previous update at `now`
+    /// and returns the smoothed colors.
+    ///
+    /// `led_counts` splits the buffer per monitor for scene cut detection,
+    /// `None` as `smoothing` passes `target` through.
+    pub fn update(
+        &mut self,
+        target: &[[u8; 3]],
+        led_counts: &[usize],
+        smoothing: Option<&Smoothing>,
+        now: Instant,
+    ) -> Vec<[u8; 3]> {
+        let last = self.last.replace(now);
+
+        let (Some(smoothing), Some(last)) = (smoothing, last) else {
+            return self.snap(target);
+        };
+        // The buffer is resized on reload before the new layout is published
+        if self.current.len() != target.len() || led_counts.iter().sum::<usize>() != target.len() {
+            return self.snap(target);
+        }
+
+        let dt = now.duration_since(last).as_secs_f32();
+        let attack = ema_factor(dt, smoothing.attack);
+        let decay = ema_factor(dt, smoothing.decay);
+
+        let mut start = 0;
+        for &len in led_counts {
+            let current = &mut self.current[start..start + len];
+            let target = &target[start..start + len];
+            start += len;
+
+            if let Some(threshold) = smoothing.scene_cut
+                && mean_change(current, target) > threshold
+            {
+                for (c, t) in current.iter_mut().zip(target) {
+                    *c = t.map(f32::from);
+                }
+                continue;
+            }
+
+            for (c, t) in current.iter_mut().zip(target) {
+                for (value, &goal) in c.iter_mut().zip(t) {
+                    let goal = goal as f32;
+                    let factor = if goal > *value { attack } else { decay };
+                    *value += (goal - *value) * factor;
+                }
+            }
+        }
+
+        self.current
+            .iter()
+            .map(|c| c.map(|v| v.round() as u8))
+            .collect()
+    }
+
+    fn snap(&mut self, target: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        self.current = target.iter().map(|t| t.map(f32::from)).collect();
+        target.to_vec()
+    }
+}
+
+/// Share of the remaining distance covered in `dt` seconds
+fn ema_factor(dt: f32, time_constant: Duration) -> f32 {
+    if time_constant.is_zero() {
+        1.0
+    } else {
+        1.0 - (-dt / time_constant.as_secs_f32()).exp()
+    }
+}
+
+/// Mean absolute difference of all channels, from 0 to 1
+fn mean_change(current: &[[f32; 3]], target: &[[u8; 3]]) -> f32 {
+    if target.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = current
+        .iter()
+        .zip(target)
+        .flat_map(|(c, t)| c.iter().zip(t).map(|(&c, &t)| (t as f32 - c).abs()))
+        .sum();
+    sum / (target.len() * 3) as f32 / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: CurrentLimit = CurrentLimit {
+        ma_per_channel: 20.0,
+        max_ma: 1500.0,
+    };
+
+    const NEUTRAL: ColorAdjustment = ColorAdjustment {
+        saturation: 1.0,
+        vibrance: 0.0,
+        hue_shift: 0.0,
+        white_threshold: None,
+    };
+
+    /// `rgb` adjusted by `adjustment`, rounded to a tenth
+    fn adjust(adjustment: &ColorAdjustment, rgb: [f32; 3]) -> [f32; 3] {
+        adjustment.apply(rgb).map(|c| (c * 10.0).round() / 10.0)
+    }
+
+    #[test]
+    fn saturation_scales_the_distance_from_grey() {
+        let half = ColorAdjustment {
+            saturation: 0.5,
+            ..NEUTRAL
+        };
+        // Hue 0, saturation 1 to 0.5, value 255
+        assert_eq!(adjust(&half, [255.0, 0.0, 0.0]), [255.0, 127.5, 127.5]);
+        let double = ColorAdjustment {
+            saturation: 2.0,
+            ..NEUTRAL
+        };
+        // Saturation 0.25 to 0.5, value 200
+        assert_eq!(
+            adjust(&double, [200.0, 150.0, 150.0]),
+            [200.0, 100.0, 100.0]
+        );
+        // Saturation stops at 1
+        assert_eq!(adjust(&double, [200.0, 50.0, 50.0]), [200.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn vibrance_mostly_changes_dull_colors() {
+        let more = ColorAdjustment {
+            vibrance: 1.0,
+            ..NEUTRAL
+        };
+        // Saturation 0.25 to 0.25 + 0.25 * 0.75
+        assert_eq!(adjust(&more, [200.0, 150.0, 150.0]), [200.0, 112.5, 112.5]);
+        assert_eq!(adjust(&more, [0.0, 255.0, 0.0]), [0.0, 255.0, 0.0]);
+        let less = ColorAdjustment {
+            vibrance: -1.0,
+            ..NEUTRAL
+        };
+        // Saturation 0.5 to 0.5 - 0.5 * 0.5
+        assert_eq!(adjust(&less, [200.0, 100.0, 100.0]), [200.0, 150.0, 150.0]);
+    }
+
+    #[test]
+    fn hue_shift_rotates_the_color() {
+        let shift = |hue_shift| ColorAdjustment {
+            hue_shift,
+            ..NEUTRAL
+        };
+        assert_eq!(adjust(&shift(120.0), [255.0, 0.0, 0.0]), [0.0, 255.0, 0.0]);
+        assert_eq!(adjust(&shift(-120.0), [255.0, 0.0, 0.0]), [0.0, 0.0, 255.0]);
+        // Hue 30 to 90
+        assert_eq!(
+            adjust(&shift(420.0), [200.0, 150.0, 100.0]),
+            [150.0, 200.0, 100.0]
+        );
+    }
+
+    #[test]
+    fn near_greys_turn_white_instead_of_a_tint() {
+        let adjustment = ColorAdjustment {
+            saturation: 3.0,
+            white_threshold: Some(0.1),
+            ..NEUTRAL
+        };
+        // Saturation 10 / 130 stays below the threshold, so it isn't boosted
+        assert_eq!(adjust(&adjustment, [130.0, 125.0, 120.0]), [130.0; 3]);
+        // Saturation 0.25 to 0.75
+        assert_eq!(
+            adjust(&adjustment, [200.0, 150.0, 150.0]),
+            [200.0, 50.0, 50.0]
+        );
+        assert_eq!(
+            adjust(&NEUTRAL, [130.0, 125.0, 120.0]),
+            [130.0, 125.0, 120.0]
+        );
+    }
+
+    fn smoothing(attack_ms: u64, decay_ms: u64, scene_cut: Option<f32>) -> Smoothing {
+        Smoothing {
+            attack: Duration::from_millis(attack_ms),
+            decay: Duration::from_millis(decay_ms),
+            scene_cut,
+        }
+    }
+
+    /// Smooths a step from black to `level` at one update every `interval_ms`
+    /// for `total_ms`
+    fn step(smoothing: &Smoothing, level: u8, interval_ms: u64, total_ms: u64) -> u8 {
+        let start = Instant::now();
+        let mut smoother = Smoother::default();
+        smoother.update(&[[0; 3]], &[1], Some(smoothing), start);
+        let mut color = [0; 3];
+        for i in 1..=total_ms / interval_ms {
+            let now = start + Duration::from_millis(i * interval_ms);
+            color = smoother.update(&[[level; 3]], &[1], Some(smoothing), now)[0];
+        }
+        color[0]
+    }
+
+    #[test]
+    fn smoothing_does_not_depend_on_the_frame_rate() {
+        let smoothing = smoothing(200, 200, None);
+        // 200 * (1 - e^(-400 / 200))
+        for interval_ms in [5, 10, 16, 40, 100, 400] {
+            assert_eq!(
+                step(&smoothing, 200, interval_ms, 400),
+                173,
+                "{interval_ms}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn attack_and_decay_are_separate() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let smoothing = smoothing(0, 1000, None);
+        let mut smoother = Smoother::default();
+        smoother.update(&[[0; 3]], &[1], Some(&smoothing), at(0));
+        // Brighter colors follow at once with no attack time
+        assert_eq!(
+            smoother.update(&[[200; 3]], &[1], Some(&smoothing), at(10)),
+            [[200; 3]]
+        );
+        // 200 * e^(-100 / 1000) while getting darker
+        assert_eq!(
+            smoother.update(&[[0; 3]], &[1], Some(&smoothing), at(110)),
+            [[181; 3]]
+        );
+    }
+
+    #[test]
+    fn scene_cut_snaps_only_its_monitor() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let smoothing = smoothing(1000, 1000, Some(0.5));
+        let mut smoother = Smoother::default();
+        smoother.update(&[[0; 3], [100; 3]], &[1, 1], Some(&smoothing), at(0));
+        // The first monitor changes by 1.0, the second by about 0.2
+        let colors = smoother.update(&[[255; 3], [150; 3]], &[1, 1], Some(&smoothing), at(100));
+        assert_eq!(colors[0], [255; 3]);
+        // 100 + 50 * (1 - e^(-100 / 1000))
+        assert_eq!(colors[1], [105; 3]);
+    }
+
+    #[test]
+    fn frames_within_the_budget_are_left_alone() {
+        let mut colors = vec![[255, 255, 255]; 10];
+        assert_eq!(limit_current(&mut colors, &LIMIT), 600.0);
+        assert_eq!(colors, vec![[255, 255, 255]; 10]);
+    }
+
+    #[test]
+    fn frames_over_the_budget_are_scaled_uniformly() {
+        let mut colors = vec![[255, 255, 255]; 100];
+        colors[0] = [255, 0, 0];
+        let draw = limit_current(&mut colors, &LIMIT);
+        assert_eq!(draw, 5960.0);
+        // 1500 / 5960 of each value, rounded down
+        assert_eq!(colors[0], [64, 0, 0]);
+        assert_eq!(colors[1], [64, 64, 64]);
+    }
+
+    #[test]
+    fn scaled_frames_stay_within_the_budget() {
+        let mut seed = 1u32;
+        for max_ma in [100.0, 333.0, 1000.0, 2500.0] {
+            let limit = CurrentLimit { max_ma, ..LIMIT };
+            for len in [1, 7, 60, 300] {
+                let mut colors: Vec<[u8; 3]> = (0..len)
+                    .map(|_| {
+                        [0; 3].map(|_| {
+                            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                            (seed >> 16) as u8
+                        })
+                    })
+                    .collect();
+                limit_current(&mut colors, &limit);
+                assert!(limit_current(&mut colors, &limit) <= max_ma);
+            }
+        }
+    }
+}
@@ -0,0 +1,1527 @@
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    MonitorRes,
+    estimate::{Estimator, SideEstimators},
+    layout::{Layout, LayoutLed},
+    letterbox::Letterbox,
+    schedule::Schedule,
+    sink::{DRGB_MAX_LEDS, WARLS_MAX_LEDS},
+};
+
+#[derive(Debug)]
+pub struct Config {
+    pub settings: Settings,
+    pub monitors: Vec<MonitorConfig>,
+    pub outputs: Vec<OutputConfig>,
+    pub profiles: HashMap<String, Profile>,
+    pub schedule: Option<Schedule>,
+    /// Whether the monitors were translated from the parallel-array format
+    legacy: bool,
+    /// Whether the outputs were translated from single output tables
+    legacy_outputs: bool,
+}
+
+/// Config file as written by the user, before the legacy format is translated
+#[derive(Debug, Deserialize)]
+struct RawConfig {
+    settings: Settings,
+    #[serde(default)]
+    monitor: Vec<MonitorConfig>,
+    led: Option<Led>,
+    indent: Option<Indent>,
+    #[serde(default)]
+    output: Vec<OutputConfig>,
+    serial: Option<SerialConfig>,
+    wled: Option<WledConfig>,
+    dmx: Option<DmxConfig>,
+    ddp: Option<DdpConfig>,
+    hyperion: Option<HyperionConfig>,
+    #[serde(default)]
+    profile: HashMap<String, Profile>,
+    schedule: Option<Schedule>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Awa,
+    Adalight,
+}
+
+impl Protocol {
+    pub fn header(self) -> &'static [u8; 3] {
+        match self {
+            Protocol::Awa => b"Awa",
+            Protocol::Adalight => b"Ada",
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Awa => write!(f, "awa"),
+            Protocol::Adalight => write!(f, "adalight"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct SerialConfig {
+    pub port: String,
+    #[serde(default = "default_serial_baud")]
+    pub baud_rate: u32,
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+fn default_serial_baud() -> u32 {
+    2_000_000
+}
+
+/// WLED realtime UDP protocol
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WledProtocol {
+    /// DRGB up to 490 LEDs, DNRGB above
+    #[default]
+    Auto,
+    Warls,
+    Drgb,
+    Dnrgb,
+}
+
+impl Display for WledProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WledProtocol::Auto => write!(f, "auto"),
+            WledProtocol::Warls => write!(f, "warls"),
+            WledProtocol::Drgb => write!(f, "drgb"),
+            WledProtocol::Dnrgb => write!(f, "dnrgb"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct WledConfig {
+    pub host: String,
+    #[serde(default = "default_wled_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: WledProtocol,
+    /// Seconds WLED waits after the last packet before returning to its own
+    /// effects, 255 keeps realtime mode until reboot
+    #[serde(default = "default_wled_timeout")]
+    pub timeout: u8,
+}
+
+fn default_wled_port() -> u16 {
+    21324
+}
+
+fn default_wled_timeout() -> u8 {
+    2
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmxProtocol {
+    E131,
+    Artnet,
+}
+
+impl DmxProtocol {
+    pub fn default_port(self) -> u16 {
+        match self {
+            DmxProtocol::E131 => 5568,
+            DmxProtocol::Artnet => 6454,
+        }
+    }
+
+    /// Highest universe number the protocol can address
+    fn max_universe(self) -> u32 {
+        match self {
+            DmxProtocol::E131 => 63999,
+            DmxProtocol::Artnet => 32767,
+        }
+    }
+}
+
+impl Display for DmxProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DmxProtocol::E131 => write!(f, "E1.31"),
+            DmxProtocol::Artnet => write!(f, "Art-Net"),
+        }
+    }
+}
+
+/// E1.31 (sACN) or Art-Net node, LEDs are packed into consecutive universes
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct DmxConfig {
+    pub protocol: DmxProtocol,
+    /// Unicast receiver, E1.31 multicasts and Art-Net broadcasts without it
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Universe of the first LED
+    #[serde(default = "default_dmx_universe")]
+    pub universe: u16,
+    /// Channels used per universe, LEDs are not split across universes
+    #[serde(default = "default_dmx_universe_size")]
+    pub universe_size: usize,
+    /// E1.31 source priority, 0 to 200
+    #[serde(default = "default_dmx_priority")]
+    pub priority: u8,
+    /// Number packets so receivers can drop late ones, Art-Net sends 0 when off
+    #[serde(default = "default_dmx_sequence")]
+    pub sequence: bool,
+}
+
+impl DmxConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(self.protocol.default_port())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct DdpConfig {
+    pub host: String,
+    #[serde(default = "default_ddp_port")]
+    pub port: u16,
+}
+
+fn default_ddp_port() -> u16 {
+    4048
+}
+
+/// How colors are handed to Hyperion/HyperHDR
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HyperionMode {
+    /// One pixel per LED in a single row, in buffer order. Hyperion samples
+    /// the row with its own layout, so that has to be one row of LEDs too.
+    Image,
+    /// Average of all LEDs as a single color
+    #[default]
+    Color,
+}
+
+impl Display for HyperionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HyperionMode::Image => write!(f, "image"),
+            HyperionMode::Color => write!(f, "color"),
+        }
+    }
+}
+
+/// Hyperion/HyperHDR flatbuffer server ambiway feeds as a prioritized source
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct HyperionConfig {
+    pub host: String,
+    #[serde(default = "default_hyperion_port")]
+    pub port: u16,
+    /// Name shown for this source in the Hyperion UI
+    #[serde(default = "default_hyperion_origin")]
+    pub origin: String,
+    /// Lower numbers win, flatbuffer clients may use 100 to 199
+    #[serde(default = "default_hyperion_priority")]
+    pub priority: i32,
+    #[serde(default)]
+    pub mode: HyperionMode,
+}
+
+fn default_hyperion_port() -> u16 {
+    19400
+}
+
+fn default_hyperion_origin() -> String {
+    "ambiway".to_string()
+}
+
+fn default_hyperion_priority() -> i32 {
+    150
+}
+
+fn default_dmx_universe() -> u16 {
+    1
+}
+
+fn default_dmx_universe_size() -> usize {
+    510
+}
+
+fn default_dmx_priority() -> u8 {
+    100
+}
+
+fn default_dmx_sequence() -> bool {
+    true
+}
+
+/// Device type of an `[[output]]` entry and its settings
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OutputKind {
+    Serial(SerialConfig),
+    Wled(WledConfig),
+    Dmx(DmxConfig),
+    Ddp(DdpConfig),
+    Hyperion(HyperionConfig),
+    /// Zones are taken from the `[[monitor]]` entries
+    Openrgb,
+}
+
+impl OutputKind {
+    /// Name of the `type` value and of the legacy table
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputKind::Serial(_) => "serial",
+            OutputKind::Wled(_) => "wled",
+            OutputKind::Dmx(_) => "dmx",
+            OutputKind::Ddp(_) => "ddp",
+            OutputKind::Hyperion(_) => "hyperion",
+            OutputKind::Openrgb => "openrgb",
+        }
+    }
+}
+
+/// One `[[output]]` entry
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct OutputConfig {
+    #[serde(flatten)]
+    pub kind: OutputKind,
+    /// Monitors whose LEDs this output gets, in this order, all if left out
+    pub monitors: Option<Vec<usize>>,
+    /// Overrides `settings.gamma`
+    pub gamma: Option<PerChannel>,
+    /// Overrides `settings.gain`
+    pub gain: Option<PerChannel>,
+    /// Overrides `settings.current_limit`
+    pub current_limit: Option<CurrentLimit>,
+}
+
+impl OutputConfig {
+    fn new(kind: OutputKind) -> Self {
+        Self {
+            kind,
+            monitors: None,
+            gamma: None,
+            gain: None,
+            current_limit: None,
+        }
+    }
+
+    /// Indices of the monitors this output gets out of `count` monitors
+    pub fn monitor_indices(&self, count: usize) -> Vec<usize> {
+        self.monitors
+            .clone()
+            .unwrap_or_else(|| (0..count).collect())
+    }
+}
+
+/// Current budget of an output's LEDs
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct CurrentLimit {
+    /// Current of one channel of one LED at full value
+    #[serde(default = "default_ma_per_channel")]
+    pub ma_per_channel: f32,
+    /// Total current the supply can give
+    pub max_ma: f32,
+}
+
+fn default_ma_per_channel() -> f32 {
+    20.0
+}
+
+/// One value for all channels, or `[r, g, b]`
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum PerChannel {
+    All(f32),
+    Rgb([f32; 3]),
+}
+
+impl PerChannel {
+    pub fn rgb(self) -> [f32; 3] {
+        match self {
+            PerChannel::All(value) => [value; 3],
+            PerChannel::Rgb(values) => values,
+        }
+    }
+}
+
+impl Default for PerChannel {
+    /// Leaves the colors unchanged as both gamma and gain
+    fn default() -> Self {
+        PerChannel::All(1.0)
+    }
+}
+
+impl Display for PerChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PerChannel::All(value) => write!(f, "{value}"),
+            PerChannel::Rgb([r, g, b]) => write!(f, "[{r}, {g}, {b}]"),
+        }
+    }
+}
+
+/// Named `[profile.<name>]` overrides of `[settings]`, switchable at runtime
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Profile {
+    pub brightness: Option<f32>,
+    pub smooth: Option<bool>,
+    pub delay_ms: Option<u64>,
+}
+
+/// One `[[monitor]]` entry
+#[derive(Clone, Debug, Deserialize)]
+pub struct MonitorConfig {
+    /// Camera device ID (/dev/video*)
+    pub cam: i32,
+    /// xrandr monitor index, defaults to the position of this entry
+    pub monitor_id: Option<usize>,
+    /// OpenRGB zone ID
+    pub zone: Option<usize>,
+    /// OpenRGB device ID, defaults to `settings.device_id`
+    pub device_id: Option<usize>,
+    /// LEDs on each side, required unless `layout` is set
+    pub led: Option<SideLeds>,
+    #[serde(default)]
+    pub indent: SideIndents,
+    /// Layout file with a region per LED, instead of `led` and `indent`
+    pub layout: Option<PathBuf>,
+    /// LEDs read from `layout` when the config is loaded
+    #[serde(skip)]
+    pub layout_leds: Vec<LayoutLed>,
+    /// Overrides `settings.estimator` for the whole monitor or single sides
+    pub estimator: Option<SideEstimators>,
+    /// Corner the strip starts at
+    #[serde(default)]
+    pub start_corner: Corner,
+    /// LEDs between `start_corner` and the first LED, along `direction`
+    #[serde(default)]
+    pub start_offset: i32,
+    #[serde(default)]
+    pub direction: Direction,
+}
+
+impl MonitorConfig {
+    /// LEDs on each side, `None` if the monitor uses a layout file
+    pub fn sides(&self) -> Option<&SideLeds> {
+        self.led.as_ref().filter(|_| self.layout.is_none())
+    }
+
+    pub fn led_count(&self) -> i32 {
+        match self.sides() {
+            Some(led) => led.total(),
+            None => self.layout_leds.len() as i32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    #[default]
+    DownLeft,
+    UpLeft,
+    UpRight,
+    DownRight,
+}
+
+/// Direction the strip runs in, seen from the front of the monitor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Clockwise, up the left side first when starting at `down_left`
+    #[default]
+    Cw,
+    /// Counter-clockwise, along the bottom first when starting at `down_left`
+    Ccw,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct SideLeds {
+    pub left: i32,
+    pub up: i32,
+    pub right: i32,
+    pub down: i32,
+    #[serde(default)]
+    pub corner: CornerLeds,
+}
+
+impl SideLeds {
+    /// LEDs on all sides and corners
+    pub fn total(&self) -> i32 {
+        let c = &self.corner;
+        self.left
+            + self.up
+            + self.right
+            + self.down
+            + c.up_left
+            + c.up_right
+            + c.down_right
+            + c.down_left
+    }
+}
+
+/// LEDs wrapped around each corner, all of them sample a square in the corner
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CornerLeds {
+    pub up_left: i32,
+    pub up_right: i32,
+    pub down_right: i32,
+    pub down_left: i32,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SideIndents {
+    pub left_up: i32,
+    pub left_down: i32,
+    pub up_left: i32,
+    pub up_right: i32,
+    pub right_up: i32,
+    pub right_down: i32,
+    pub down_left: i32,
+    pub down_right: i32,
+}
+
+/// Legacy `[led]` table, one entry per monitor
+#[derive(Debug, Deserialize)]
+struct Led {
+    left: Vec<i32>,
+    up: Vec<i32>,
+    right: Vec<i32>,
+    down: Vec<i32>,
+}
+
+/// Legacy `[indent]` table, one entry per monitor
+#[derive(Debug, Deserialize)]
+struct Indent {
+    left_up: Vec<i32>,
+    left_down: Vec<i32>,
+    up_left: Vec<i32>,
+    up_right: Vec<i32>,
+    right_up: Vec<i32>,
+    right_down: Vec<i32>,
+    down_left: Vec<i32>,
+    down_right: Vec<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    #[serde(default = "default_size")]
+    pub size: i32,
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    #[serde(default = "default_smooth")]
+    pub smooth: bool,
+    /// Time constant of the smoothing in milliseconds
+    #[serde(default = "default_smooth_ms")]
+    pub smooth_ms: u64,
+    /// Time constant when a channel gets brighter, defaults to `smooth_ms`
+    pub attack_ms: Option<u64>,
+    /// Time constant when a channel gets darker, defaults to `smooth_ms`
+    pub decay_ms: Option<u64>,
+    /// Mean change of a monitor's colors, 0 to 1, above which smoothing is
+    /// skipped so scene cuts show up at once
+    pub scene_cut: Option<f32>,
+    /// How the color of each region is computed
+    #[serde(default)]
+    pub estimator: Estimator,
+    /// Saturation multiplier applied to each region's color
+    #[serde(default = "default_saturation")]
+    pub saturation: f32,
+    /// Extra saturation for dull colors, -1 to 1
+    #[serde(default)]
+    pub vibrance: f32,
+    /// Hue rotation in degrees
+    #[serde(default)]
+    pub hue_shift: f32,
+    /// Saturation, 0 to 1, below which colors become white
+    pub white_threshold: Option<f32>,
+    /// Moves the sampling regions inward past black bars
+    #[serde(default)]
+    pub letterbox: Letterbox,
+    /// Gamma of the LEDs, applied by every output after averaging
+    #[serde(default)]
+    pub gamma: PerChannel,
+    /// Per-channel multiplier to correct the white point of the LEDs
+    #[serde(default)]
+    pub gain: PerChannel,
+    /// Scales each output's frame down when it would draw more current
+    pub current_limit: Option<CurrentLimit>,
+    #[serde(default)]
+    pub device_id: usize,
+    // Legacy parallel arrays, translated into `[[monitor]]` entries
+    cams: Option<Vec<i32>>,
+    zone_id_list: Option<Vec<usize>>,
+    monitor_id_list: Option<Vec<usize>>,
+}
+
+fn default_size() -> i32 {
+    50
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+fn default_delay_ms() -> u64 {
+    95
+}
+
+fn default_smooth() -> bool {
+    true
+}
+
+fn default_smooth_ms() -> u64 {
+    150
+}
+
+fn default_saturation() -> f32 {
+    1.0
+}
+
+/// Problem found in the config, with the TOML key it refers to
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NoConfigDir,
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Layout(PathBuf, String),
+    Invalid(Vec<ConfigIssue>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::NoConfigDir => write!(f, "Failed to get config path"),
+            ConfigError::Read(path, e) => write!(f, "Failed to read config file {path:?}: {e}"),
+            ConfigError::Parse(path, e) => write!(f, "Failed to parse config file {path:?}: {e}"),
+            ConfigError::Layout(path, e) => write!(f, "Failed to load layout file {path:?}: {e}"),
+            ConfigError::Invalid(issues) => {
+                write!(f, "Invalid config ({} problem(s)):", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  {issue}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl RawConfig {
+    fn into_config(mut self) -> Result<Config, Vec<ConfigIssue>> {
+        let legacy = self.led.is_some() || self.indent.is_some() || self.settings.cams.is_some();
+
+        let monitors = match (legacy, self.monitor.is_empty()) {
+            (true, false) => {
+                return Err(vec![ConfigIssue::new(
+                    "monitor",
+                    "[[monitor]] entries can't be mixed with [led], [indent] and settings.cams",
+                )]);
+            }
+            (false, true) => {
+                return Err(vec![ConfigIssue::new(
+                    "monitor",
+                    "no [[monitor]] entries configured",
+                )]);
+            }
+            (false, false) => self.monitor,
+            (true, true) => self.translate_legacy()?,
+        };
+
+        let legacy_outputs: Vec<OutputKind> = [
+            self.serial.map(OutputKind::Serial),
+            self.wled.map(OutputKind::Wled),
+            self.dmx.map(OutputKind::Dmx),
+            self.ddp.map(OutputKind::Ddp),
+            self.hyperion.map(OutputKind::Hyperion),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let legacy_output_names: Vec<String> = legacy_outputs
+            .iter()
+            .map(|kind| format!("[{}]", kind.name()))
+            .collect();
+
+        let outputs = match (legacy_outputs.is_empty(), self.output.is_empty()) {
+            (false, false) => {
+                return Err(vec![ConfigIssue::new(
+                    "output",
+                    format!(
+                        "[[output]] entries can't be mixed with {}",
+                        legacy_output_names.join(", ")
+                    ),
+                )]);
+            }
+            // OpenRGB was the only output before any other was supported
+            (true, true) => vec![OutputConfig::new(OutputKind::Openrgb)],
+            (true, false) => self.output,
+            (false, true) => legacy_outputs.into_iter().map(OutputConfig::new).collect(),
+        };
+
+        Ok(Config {
+            settings: self.settings,
+            monitors,
+            outputs,
+            profiles: self.profile,
+            schedule: self.schedule,
+            legacy,
+            legacy_outputs: !legacy_output_names.is_empty(),
+        })
+    }
+
+    /// Builds `[[monitor]]` entries from the parallel-array format
+    fn translate_legacy(&mut self) -> Result<Vec<MonitorConfig>, Vec<ConfigIssue>> {
+        let mut issues = Vec::new();
+        let cams = self.settings.cams.take();
+        let led = self.led.take();
+        let indent = self.indent.take();
+        let zone_id_list = self.settings.zone_id_list.take();
+        let monitor_id_list = self.settings.monitor_id_list.take();
+
+        if cams.is_none() {
+            issues.push(ConfigIssue::new("settings.cams", "missing"));
+        }
+        if led.is_none() {
+            issues.push(ConfigIssue::new("led", "missing table"));
+        }
+        if indent.is_none() {
+            issues.push(ConfigIssue::new("indent", "missing table"));
+        }
+        let (Some(cams), Some(led), Some(indent)) = (cams, led, indent) else {
+            return Err(issues);
+        };
+
+        let mut lengths = vec![
+            ("led.left", led.left.len()),
+            ("led.up", led.up.len()),
+            ("led.right", led.right.len()),
+            ("led.down", led.down.len()),
+            ("indent.left_up", indent.left_up.len()),
+            ("indent.left_down", indent.left_down.len()),
+            ("indent.up_left", indent.up_left.len()),
+            ("indent.up_right", indent.up_right.len()),
+            ("indent.right_up", indent.right_up.len()),
+            ("indent.right_down", indent.right_down.len()),
+            ("indent.down_left", indent.down_left.len()),
+            ("indent.down_right", indent.down_right.len()),
+        ];
+        if let Some(ids) = &zone_id_list {
+            lengths.push(("settings.zone_id_list", ids.len()));
+        }
+        if let Some(ids) = &monitor_id_list {
+            lengths.push(("settings.monitor_id_list", ids.len()));
+        }
+        for (key, len) in lengths {
+            if len != cams.len() {
+                issues.push(ConfigIssue::new(
+                    key,
+                    format!(
+                        "has {len} entries, expected {} (one per entry in settings.cams)",
+                        cams.len()
+                    ),
+                ));
+            }
+        }
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
+        Ok(cams
+            .iter()
+            .enumerate()
+            .map(|(i, &cam)| MonitorConfig {
+                cam,
+                monitor_id: monitor_id_list.as_ref().map(|ids| ids[i]),
+                zone: zone_id_list.as_ref().map(|ids| ids[i]),
+                device_id: None,
+                led: Some(SideLeds {
+                    left: led.left[i],
+                    up: led.up[i],
+                    right: led.right[i],
+                    down: led.down[i],
+                    corner: CornerLeds::default(),
+                }),
+                indent: SideIndents {
+                    left_up: indent.left_up[i],
+                    left_down: indent.left_down[i],
+                    up_left: indent.up_left[i],
+                    up_right: indent.up_right[i],
+                    right_up: indent.right_up[i],
+                    right_down: indent.right_down[i],
+                    down_left: indent.down_left[i],
+                    down_right: indent.down_right[i],
+                },
+                layout: None,
+                layout_leds: Vec::new(),
+                estimator: None,
+                start_corner: Corner::default(),
+                start_offset: 0,
+                direction: Direction::default(),
+            })
+            .collect())
+    }
+}
+
+impl Config {
+    /// xrandr monitor index of every configured monitor
+    pub fn monitor_ids(&self) -> Vec<usize> {
+        self.monitors
+            .iter()
+            .enumerate()
+            .map(|(i, m)| m.monitor_id.unwrap_or(i))
+            .collect()
+    }
+
+    /// Whether switching to `other` changes values that are only read at startup
+    pub fn needs_restart(&self, other: &Config) -> bool {
+        self.outputs.len() != other.outputs.len()
+            || self
+                .outputs
+                .iter()
+                .zip(&other.outputs)
+                .any(|(a, b)| a.kind != b.kind || a.monitors != b.monitors)
+            || self.settings.device_id != other.settings.device_id
+            || self.monitor_ids() != other.monitor_ids()
+            || self
+                .monitors
+                .iter()
+                .zip(&other.monitors)
+                .any(|(a, b)| a.cam != b.cam || a.zone != b.zone || a.device_id != b.device_id)
+    }
+
+    /// Layout files of the monitors, for the config loaded from `path`
+    pub fn layout_paths(&self, path: &Path) -> Vec<PathBuf> {
+        self.monitors
+            .iter()
+            .filter_map(|m| m.layout.as_deref())
+            .map(|layout| relative_to(path, layout))
+            .collect()
+    }
+
+    /// Whether an OpenRGB output gets the LEDs of monitor `i`
+    fn uses_openrgb(&self, i: usize) -> bool {
+        self.outputs.iter().any(|o| {
+            o.kind == OutputKind::Openrgb && o.monitor_indices(self.monitors.len()).contains(&i)
+        })
+    }
+
+    /// Checks the LED counts of monitor `i`
+    fn validate_leds(&self, i: usize, led: &SideLeds, issues: &mut Vec<ConfigIssue>) {
+        let sides = [
+            ("led.left", led.left),
+            ("led.up", led.up),
+            ("led.right", led.right),
+            ("led.down", led.down),
+        ];
+        for (field, count) in sides {
+            if count < 1 {
+                issues.push(ConfigIssue::new(
+                    self.monitor_key(i, field),
+                    format!("must be at least 1, got {count}"),
+                ));
+            }
+        }
+        let corners = [
+            ("led.corner.up_left", led.corner.up_left),
+            ("led.corner.up_right", led.corner.up_right),
+            ("led.corner.down_right", led.corner.down_right),
+            ("led.corner.down_left", led.corner.down_left),
+        ];
+        for (field, count) in corners {
+            if count < 0 {
+                issues.push(ConfigIssue::new(
+                    self.monitor_key(i, field),
+                    format!("must not be negative, got {count}"),
+                ));
+            }
+        }
+    }
+
+    /// Checks the LEDs read from the layout file of monitor `i`
+    fn validate_layout(&self, i: usize, m: &MonitorConfig, issues: &mut Vec<ConfigIssue>) {
+        let key = self.monitor_key(i, "layout");
+        if m.layout_leds.is_empty() {
+            issues.push(ConfigIssue::new(key.clone(), "has no LEDs"));
+        }
+        for (j, led) in m.layout_leds.iter().enumerate() {
+            if !led.is_valid() {
+                issues.push(ConfigIssue::new(
+                    key.clone(),
+                    format!(
+                        "LED {j} has rect {:?}, expected x1 < x2 and y1 < y2 between 0 and 1",
+                        led.rect
+                    ),
+                ));
+            }
+            if !led.weight.is_finite() || led.weight < 0.0 {
+                issues.push(ConfigIssue::new(
+                    key.clone(),
+                    format!(
+                        "LED {j} has weight {}, expected a non-negative number",
+                        led.weight
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// TOML key of `field` for monitor `i`, in whichever format the user wrote
+    fn monitor_key(&self, i: usize, field: &str) -> String {
+        if self.legacy {
+            let key = match field {
+                "cam" => "settings.cams",
+                "zone" => "settings.zone_id_list",
+                "monitor_id" => "settings.monitor_id_list",
+                other => other,
+            };
+            format!("{key}[{i}]")
+        } else {
+            format!("monitor[{i}].{field}")
+        }
+    }
+
+    /// TOML key of `field` for output `i`, in whichever format the user wrote
+    fn output_key(&self, i: usize, field: &str) -> String {
+        if self.legacy_outputs {
+            format!("{}.{field}", self.outputs[i].kind.name())
+        } else {
+            format!("output[{i}].{field}")
+        }
+    }
+
+    /// Checks the device settings of output `i`, which gets `total` LEDs
+    fn validate_output(
+        &self,
+        i: usize,
+        kind: &OutputKind,
+        total: i32,
+        issues: &mut Vec<ConfigIssue>,
+    ) {
+        match kind {
+            OutputKind::Wled(wled) => {
+                let limit = match wled.protocol {
+                    WledProtocol::Warls => Some(WARLS_MAX_LEDS),
+                    WledProtocol::Drgb => Some(DRGB_MAX_LEDS),
+                    WledProtocol::Auto | WledProtocol::Dnrgb => None,
+                };
+                if let Some(limit) = limit
+                    && total > limit as i32
+                {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "protocol"),
+                        format!(
+                            "{} supports at most {limit} LEDs, got {total}, use dnrgb or auto",
+                            wled.protocol
+                        ),
+                    ));
+                }
+            }
+            OutputKind::Dmx(dmx) => {
+                if !(3..=512).contains(&dmx.universe_size) {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "universe_size"),
+                        format!("must be between 3 and 512, got {}", dmx.universe_size),
+                    ));
+                } else {
+                    let leds_per_universe = dmx.universe_size / 3;
+                    let universes = (total.max(0) as usize).div_ceil(leds_per_universe);
+                    let last = dmx.universe as u32 + universes.saturating_sub(1) as u32;
+                    if last > dmx.protocol.max_universe() {
+                        issues.push(ConfigIssue::new(
+                            self.output_key(i, "universe"),
+                            format!(
+                                "{total} LEDs need universes {} to {last}, {} ends at {}",
+                                dmx.universe,
+                                dmx.protocol,
+                                dmx.protocol.max_universe()
+                            ),
+                        ));
+                    }
+                }
+                if dmx.protocol == DmxProtocol::E131 && dmx.universe == 0 {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "universe"),
+                        "E1.31 universes start at 1",
+                    ));
+                }
+                if dmx.priority > 200 {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "priority"),
+                        format!("must be between 0 and 200, got {}", dmx.priority),
+                    ));
+                }
+                if dmx.protocol == DmxProtocol::E131 && !dmx.sequence {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "sequence"),
+                        "E1.31 receivers drop packets without sequence numbers",
+                    ));
+                }
+            }
+            OutputKind::Hyperion(hyperion) => {
+                if !(100..=199).contains(&hyperion.priority) {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "priority"),
+                        format!("must be between 100 and 199, got {}", hyperion.priority),
+                    ));
+                }
+                if hyperion.origin.is_empty() {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "origin"),
+                        "must not be empty",
+                    ));
+                }
+            }
+            OutputKind::Serial(_) | OutputKind::Ddp(_) | OutputKind::Openrgb => {}
+        }
+    }
+
+    /// Checks every value, returning all problems at once.
+    ///
+    /// `available` are all monitors reported by xrandr. Checks that need the
+    /// monitor resolution are skipped when it is `None`.
+    pub fn validate(&self, available: Option<&[MonitorRes]>) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let settings = &self.settings;
+
+        if settings.size < 1 {
+            issues.push(ConfigIssue::new(
+                "settings.size",
+                format!("must be at least 1, got {}", settings.size),
+            ));
+        }
+        if !settings.brightness.is_finite() || settings.brightness < 0.0 {
+            issues.push(ConfigIssue::new(
+                "settings.brightness",
+                format!("must be a non-negative number, got {}", settings.brightness),
+            ));
+        }
+
+        if let Some(scene_cut) = settings.scene_cut
+            && !(scene_cut > 0.0 && scene_cut <= 1.0)
+        {
+            issues.push(ConfigIssue::new(
+                "settings.scene_cut",
+                format!("must be above 0 and at most 1, got {scene_cut}"),
+            ));
+        }
+        if !settings.saturation.is_finite() || settings.saturation < 0.0 {
+            issues.push(ConfigIssue::new(
+                "settings.saturation",
+                format!("must be a non-negative number, got {}", settings.saturation),
+            ));
+        }
+        if !(-1.0..=1.0).contains(&settings.vibrance) {
+            issues.push(ConfigIssue::new(
+                "settings.vibrance",
+                format!("must be between -1 and 1, got {}", settings.vibrance),
+            ));
+        }
+        if !settings.hue_shift.is_finite() {
+            issues.push(ConfigIssue::new(
+                "settings.hue_shift",
+                format!("must be a number of degrees, got {}", settings.hue_shift),
+            ));
+        }
+        if let Some(threshold) = settings.white_threshold
+            && !(0.0..=1.0).contains(&threshold)
+        {
+            issues.push(ConfigIssue::new(
+                "settings.white_threshold",
+                format!("must be between 0 and 1, got {threshold}"),
+            ));
+        }
+        check_gamma(&mut issues, "settings.gamma".to_string(), settings.gamma);
+        check_gain(&mut issues, "settings.gain".to_string(), settings.gain);
+        if let Some(limit) = settings.current_limit {
+            check_current_limit(&mut issues, "settings.current_limit", limit);
+        }
+
+        for (name, profile) in &self.profiles {
+            if let Some(brightness) = profile.brightness
+                && (!brightness.is_finite() || brightness < 0.0)
+            {
+                issues.push(ConfigIssue::new(
+                    format!("profile.{name}.brightness"),
+                    format!("must be a non-negative number, got {brightness}"),
+                ));
+            }
+        }
+
+        if let Some(schedule) = &self.schedule {
+            validate_schedule(schedule, &mut issues);
+        }
+
+        let led_counts: Vec<i32> = self.monitors.iter().map(|m| m.led_count()).collect();
+        for (i, output) in self.outputs.iter().enumerate() {
+            if let Some(monitors) = &output.monitors {
+                if monitors.is_empty() {
+                    issues.push(ConfigIssue::new(
+                        self.output_key(i, "monitors"),
+                        "must list at least one monitor",
+                    ));
+                }
+                for &m in monitors {
+                    if m >= self.monitors.len() {
+                        issues.push(ConfigIssue::new(
+                            self.output_key(i, "monitors"),
+                            format!("monitor {m} not found ({} configured)", self.monitors.len()),
+                        ));
+                    }
+                }
+            }
+            let total: i32 = output
+                .monitor_indices(self.monitors.len())
+                .iter()
+                .filter_map(|&m| led_counts.get(m))
+                .sum();
+            self.validate_output(i, &output.kind, total, &mut issues);
+            if let Some(gamma) = output.gamma {
+                check_gamma(&mut issues, self.output_key(i, "gamma"), gamma);
+            }
+            if let Some(gain) = output.gain {
+                check_gain(&mut issues, self.output_key(i, "gain"), gain);
+            }
+            if let Some(limit) = output.current_limit {
+                check_current_limit(&mut issues, &self.output_key(i, "current_limit"), limit);
+            }
+        }
+
+        for (i, (m, id)) in self.monitors.iter().zip(self.monitor_ids()).enumerate() {
+            let led = match (&m.led, &m.layout) {
+                (Some(led), None) => Some(led),
+                (None, Some(_)) => {
+                    self.validate_layout(i, m, &mut issues);
+                    None
+                }
+                (Some(_), Some(_)) => {
+                    issues.push(ConfigIssue::new(
+                        self.monitor_key(i, "layout"),
+                        "can't be combined with led",
+                    ));
+                    None
+                }
+                (None, None) => {
+                    issues.push(ConfigIssue::new(
+                        self.monitor_key(i, "led"),
+                        "required unless layout is set",
+                    ));
+                    None
+                }
+            };
+            if let Some(led) = led {
+                self.validate_leds(i, led, &mut issues);
+            }
+
+            let indents = [
+                ("indent.left_up", m.indent.left_up),
+                ("indent.left_down", m.indent.left_down),
+                ("indent.up_left", m.indent.up_left),
+                ("indent.up_right", m.indent.up_right),
+                ("indent.right_up", m.indent.right_up),
+                ("indent.right_down", m.indent.right_down),
+                ("indent.down_left", m.indent.down_left),
+                ("indent.down_right", m.indent.down_right),
+            ];
+            for (field, indent) in indents {
+                if indent < 0 {
+                    issues.push(ConfigIssue::new(
+                        self.monitor_key(i, field),
+                        format!("must not be negative, got {indent}"),
+                    ));
+                }
+            }
+
+            if self.uses_openrgb(i) && m.zone.is_none() {
+                issues.push(ConfigIssue::new(
+                    self.monitor_key(i, "zone"),
+                    "required for OpenRGB output",
+                ));
+            }
+
+            let Some(available) = available else {
+                continue;
+            };
+            let Some(res) = available.get(id) else {
+                issues.push(ConfigIssue::new(
+                    self.monitor_key(i, "monitor_id"),
+                    format!("monitor {id} not found ({} available)", available.len()),
+                ));
+                continue;
+            };
+
+            if settings.size > res.width.min(res.height) {
+                issues.push(ConfigIssue::new(
+                    "settings.size",
+                    format!(
+                        "{}px doesn't fit inside the {}x{} monitor {id}",
+                        settings.size, res.width, res.height
+                    ),
+                ));
+            }
+
+            let Some(led) = led else {
+                continue;
+            };
+            let ind = &m.indent;
+            let spans = [
+                (
+                    "left",
+                    led.left,
+                    ("left_up", ind.left_up),
+                    ("left_down", ind.left_down),
+                    res.height,
+                ),
+                (
+                    "up",
+                    led.up,
+                    ("up_left", ind.up_left),
+                    ("up_right", ind.up_right),
+                    res.width,
+                ),
+                (
+                    "right",
+                    led.right,
+                    ("right_up", ind.right_up),
+                    ("right_down", ind.right_down),
+                    res.height,
+                ),
+                (
+                    "down",
+                    led.down,
+                    ("down_left", ind.down_left),
+                    ("down_right", ind.down_right),
+                    res.width,
+                ),
+            ];
+            for (side, count, (start, start_indent), (end, end_indent), length) in spans {
+                let inner = length - start_indent - end_indent;
+                if inner < 1 {
+                    issues.push(ConfigIssue::new(
+                        format!(
+                            "{} + {}",
+                            self.monitor_key(i, &format!("indent.{start}")),
+                            self.monitor_key(i, &format!("indent.{end}"))
+                        ),
+                        format!("{start_indent} + {end_indent} leave no room on a {length}px side"),
+                    ));
+                } else if count > inner {
+                    issues.push(ConfigIssue::new(
+                        self.monitor_key(i, &format!("led.{side}")),
+                        format!("{count} LEDs don't fit into {inner}px between the indents"),
+                    ));
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+fn check_gamma(issues: &mut Vec<ConfigIssue>, key: String, gamma: PerChannel) {
+    if gamma.rgb().iter().any(|g| !g.is_finite() || *g <= 0.0) {
+        issues.push(ConfigIssue::new(
+            key,
+            format!("must be a positive number, got {gamma}"),
+        ));
+    }
+}
+
+fn check_gain(issues: &mut Vec<ConfigIssue>, key: String, gain: PerChannel) {
+    if gain.rgb().iter().any(|g| !g.is_finite() || *g < 0.0) {
+        issues.push(ConfigIssue::new(
+            key,
+            format!("must be a non-negative number, got {gain}"),
+        ));
+    }
+}
+
+fn validate_schedule(schedule: &Schedule, issues: &mut Vec<ConfigIssue>) {
+    if schedule.points.is_empty() {
+        issues.push(ConfigIssue::new(
+            "schedule.point",
+            "must list at least one point",
+        ));
+    }
+    if schedule.uses_sun() {
+        for (field, value) in [
+            ("latitude", schedule.latitude),
+            ("longitude", schedule.longitude),
+        ] {
+            if value.is_none() {
+                issues.push(ConfigIssue::new(
+                    format!("schedule.{field}"),
+                    "is needed for points at sunrise or sunset",
+                ));
+            }
+        }
+    }
+    if let Some(latitude) = schedule.latitude
+        && !(-90.0..=90.0).contains(&latitude)
+    {
+        issues.push(ConfigIssue::new(
+            "schedule.latitude",
+            format!("must be between -90 and 90, got {latitude}"),
+        ));
+    }
+    if let Some(longitude) = schedule.longitude
+        && !(-180.0..=180.0).contains(&longitude)
+    {
+        issues.push(ConfigIssue::new(
+            "schedule.longitude",
+            format!("must be between -180 and 180, got {longitude}"),
+        ));
+    }
+
+    for (i, point) in schedule.points.iter().enumerate() {
+        if !(1000.0..=40000.0).contains(&point.temperature) {
+            issues.push(ConfigIssue::new(
+                format!("schedule.point[{i}].temperature"),
+                format!("must be between 1000 and 40000, got {}", point.temperature),
+            ));
+        }
+        if !point.brightness.is_finite() || point.brightness < 0.0 {
+            issues.push(ConfigIssue::new(
+                format!("schedule.point[{i}].brightness"),
+                format!("must be a non-negative number, got {}", point.brightness),
+            ));
+        }
+    }
+}
+
+fn check_current_limit(issues: &mut Vec<ConfigIssue>, key: &str, limit: CurrentLimit) {
+    let fields = [
+        ("ma_per_channel", limit.ma_per_channel),
+        ("max_ma", limit.max_ma),
+    ];
+    for (field, value) in fields {
+        if !value.is_finite() || value <= 0.0 {
+            issues.push(ConfigIssue::new(
+                format!("{key}.{field}"),
+                format!("must be a positive number, got {value}"),
+            ));
+        }
+    }
+}
+
+pub fn load_config_from_file(path: &Path) -> Result<Config, ConfigError> {
+    let config_str =
+        fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    let raw: RawConfig =
+        toml::from_str(&config_str).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+    let mut config = raw.into_config().map_err(ConfigError::Invalid)?;
+
+    for monitor in &mut config.monitors {
+        if let Some(layout) = &monitor.layout {
+            let layout = relative_to(path, layout);
+            monitor.layout_leds = Layout::load(&layout)
+                .map_err(|e| ConfigError::Layout(layout, e))?
+                .led;
+        }
+    }
+    Ok(config)
+}
+
+/// `file` as given in the config at `config_path`, relative paths start in its directory
+fn relative_to(config_path: &Path, file: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join(file)
+}
+
+pub fn get_config_path() -> Option<PathBuf> {
+    let proj_dirs = ProjectDirs::from("com", "timasoft", "ambiway")?;
+    Some(proj_dirs.config_dir().join("config.toml"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Config from the text of a `config.toml`, without loading layout files
+    pub(crate) fn parse(text: &str) -> Config {
+        toml::from_str::<RawConfig>(text)
+            .unwrap()
+            .into_config()
+            .unwrap()
+    }
+
+    #[test]
+    fn only_art_net_can_turn_off_sequence_numbers() {
+        let config = |protocol: &str| {
+            parse(&format!(
+                "[settings]\n\
+                 [[monitor]]\n\
+                 cam = 0\n\
+                 led = {{ left = 2, up = 2, right = 2, down = 2 }}\n\
+                 [[output]]\n\
+                 type = \"dmx\"\n\
+                 protocol = \"{protocol}\"\n\
+                 sequence = false\n"
+            ))
+        };
+        assert!(config("artnet").validate(None).is_empty());
+        let issues = config("e131").validate(None);
+        let keys: Vec<&str> = issues.iter().map(|i| i.key.as_str()).collect();
+        assert_eq!(keys, ["output[0].sequence"]);
+    }
+
+    /// Config in the format of the first releases
+    const LEGACY: &str = "\
+        [led]\n\
+        left = [36, 41]\n\
+        up = [62, 76]\n\
+        right = [36, 42]\n\
+        down = [62, 81]\n\
+        [indent]\n\
+        left_up = [0, 40]\n\
+        left_down = [0, 0]\n\
+        up_left = [0, 0]\n\
+        up_right = [0, 0]\n\
+        right_up = [0, 40]\n\
+        right_down = [0, 0]\n\
+        down_left = [0, 0]\n\
+        down_right = [0, 0]\n\
+        [settings]\n\
+        size = 50\n\
+        brightness = 0.25\n\
+        smooth = false\n\
+        cams = [2, 3]\n\
+        device_id = 0\n\
+        zone_id_list = [1, 2]\n";
+
+    #[test]
+    fn legacy_config_becomes_monitor_tables() {
+        let legacy = parse(LEGACY);
+        let tables = parse(
+            "[settings]\n\
+             size = 50\n\
+             brightness = 0.25\n\
+             smooth = false\n\
+             device_id = 0\n\
+             [[monitor]]\n\
+             cam = 2\n\
+             zone = 1\n\
+             led = { left = 36, up = 62, right = 36, down = 62 }\n\
+             [[monitor]]\n\
+             cam = 3\n\
+             zone = 2\n\
+             led = { left = 41, up = 76, right = 42, down = 81 }\n\
+             indent = { left_up = 40, right_up = 40 }\n",
+        );
+        assert_eq!(
+            format!("{:?}", legacy.monitors),
+            format!("{:?}", tables.monitors)
+        );
+        assert_eq!(legacy.outputs, tables.outputs);
+        assert_eq!(legacy.monitor_ids(), [0, 1]);
+        assert_eq!(legacy.settings.size, 50);
+        assert_eq!(legacy.settings.brightness, 0.25);
+        assert!(!legacy.settings.smooth);
+        assert_eq!(legacy.settings.device_id, tables.settings.device_id);
+        assert!(legacy.validate(None).is_empty());
+    }
+
+    /// Keys of all issues `config` has with one 1920x1080 monitor attached
+    fn issue_keys(config: &Config) -> Vec<String> {
+        let available = [crate::MonitorRes {
+            width: 1920,
+            height: 1080,
+        }];
+        config
+            .validate(Some(&available))
+            .into_iter()
+            .map(|issue| issue.key)
+            .collect()
+    }
+
+    #[test]
+    fn every_legacy_issue_is_reported_at_once() {
+        let text = LEGACY
+            .replace("brightness = 0.25", "brightness = -1")
+            .replace("left = [36, 41]", "left = [0, 41]")
+            .replace("left_up = [0, 40]", "left_up = [-5, 40]");
+        assert_eq!(
+            issue_keys(&parse(&text)),
+            [
+                "settings.brightness",
+                "led.left[0]",
+                "indent.left_up[0]",
+                "settings.monitor_id_list[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_monitor_table_issue_is_reported_at_once() {
+        let config = parse(
+            "[settings]\n\
+             brightness = -1\n\
+             [[monitor]]\n\
+             cam = 0\n\
+             led = { left = 0, up = 2, right = 2, down = 2 }\n\
+             indent = { left_up = -5 }\n\
+             [[monitor]]\n\
+             cam = 1\n\
+             led = { left = 2, up = 2, right = 2, down = 2 }\n\
+             [[output]]\n\
+             type = \"hyperion\"\n\
+             host = \"localhost\"\n\
+             priority = 50\n\
+             [[output]]\n\
+             type = \"openrgb\"\n\
+             monitors = [1]\n",
+        );
+        assert_eq!(
+            issue_keys(&config),
+            [
+                "settings.brightness",
+                "output[0].priority",
+                "monitor[0].led.left",
+                "monitor[0].indent.left_up",
+                "monitor[1].zone",
+                "monitor[1].monitor_id",
+            ]
+        );
+    }
+}
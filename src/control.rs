@@ -0,0 +1,328 @@
+use chrono::Local;
+use clap::{Subcommand, ValueEnum};
+use std::{
+    fmt::{Display, Write as _},
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixListener,
+    sync::{Notify, watch},
+};
+
+use crate::{letterbox::Letterbox, reload::LiveSettings, sink::SinkHealth};
+
+/// Command sent to the daemon over the control socket, one per line
+#[derive(Clone, Debug, Subcommand)]
+pub enum Request {
+    /// Pause the ambilight
+    Pause,
+    /// Resume the ambilight
+    Resume,
+    /// Toggle pause
+    Toggle,
+    /// Set the brightness multiplier
+    Brightness { value: f32 },
+    /// Turn color smoothing on or off
+    Smooth { state: Switch },
+    /// Switch to a `[profile.<name>]`, `default` goes back to `[settings]`
+    Profile { name: String },
+    /// Black bar handling: `auto`, `off` or `top,bottom[,left,right]` in pixels
+    Letterbox { mode: Letterbox },
+    /// Show the daemon state
+    Status,
+    /// Reload the config file
+    Reload,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Request::Pause => write!(f, "pause"),
+            Request::Resume => write!(f, "resume"),
+            Request::Toggle => write!(f, "toggle"),
+            Request::Brightness { value } => write!(f, "brightness {value}"),
+            Request::Smooth { state: Switch::On } => write!(f, "smooth on"),
+            Request::Smooth { state: Switch::Off } => write!(f, "smooth off"),
+            Request::Profile { name } => write!(f, "profile {name}"),
+            Request::Letterbox { mode } => write!(f, "letterbox {mode}"),
+            Request::Status => write!(f, "status"),
+            Request::Reload => write!(f, "reload"),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many arguments for {command}"));
+        }
+
+        let request = match (command, arg) {
+            ("pause", None) => Request::Pause,
+            ("resume", None) => Request::Resume,
+            ("toggle", None) => Request::Toggle,
+            ("status", None) => Request::Status,
+            ("reload", None) => Request::Reload,
+            ("brightness", Some(value)) => Request::Brightness {
+                value: value
+                    .parse()
+                    .map_err(|_| format!("invalid brightness {value}"))?,
+            },
+            ("smooth", Some("on")) => Request::Smooth { state: Switch::On },
+            ("smooth", Some("off")) => Request::Smooth { state: Switch::Off },
+            ("profile", Some(name)) => Request::Profile {
+                name: name.to_string(),
+            },
+            ("letterbox", Some(mode)) => Request::Letterbox {
+                mode: mode.parse()?,
+            },
+            _ => return Err(format!("unknown command: {line}")),
+        };
+        Ok(request)
+    }
+}
+
+/// Everything the control socket can read or change
+pub struct Control {
+    pub manual_pause: Arc<AtomicBool>,
+    pub screen_off: Arc<AtomicBool>,
+    /// Notified when the pause state changes
+    pub pause_changed: Arc<Notify>,
+    pub live: watch::Sender<Arc<LiveSettings>>,
+    /// Frames per second of each camera, stored as `f32` bits
+    pub camera_fps: Arc<Vec<AtomicU32>>,
+    pub sinks: Vec<Arc<SinkHealth>>,
+    pub reload: Arc<Notify>,
+}
+
+impl Control {
+    fn handle(&self, line: &str) -> String {
+        let request = match line.parse::<Request>() {
+            Ok(request) => request,
+            Err(e) => return format!("error: {e}\n"),
+        };
+
+        match request {
+            Request::Pause => self.set_paused(true),
+            Request::Resume => self.set_paused(false),
+            Request::Toggle => {
+                let paused = !self.manual_pause.load(Ordering::Relaxed);
+                self.set_paused(paused);
+            }
+            Request::Brightness { value } => {
+                if !value.is_finite() || value < 0.0 {
+                    return format!(
+                        "error: brightness must be a non-negative number, got {value}\n"
+                    );
+                }
+                self.modify(|s| s.brightness = value);
+            }
+            Request::Smooth { state } => {
+                self.modify(|s| s.smooth = matches!(state, Switch::On));
+            }
+            Request::Profile { name } => {
+                let name = (name != "default").then_some(name);
+                let mut settings = LiveSettings::clone(&self.live.borrow());
+                if let Err(e) = settings.set_profile(name.as_deref()) {
+                    return format!("error: {e}\n");
+                }
+                self.live.send_replace(Arc::new(settings));
+            }
+            Request::Letterbox { mode } => self.modify(|s| s.letterbox = mode),
+            Request::Status => return self.status(),
+            Request::Reload => self.reload.notify_one(),
+        }
+        println!("[Control] {line}");
+        "ok\n".to_string()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.manual_pause.store(paused, Ordering::Relaxed);
+        self.pause_changed.notify_one();
+    }
+
+    fn modify(&self, f: impl FnOnce(&mut LiveSettings)) {
+        let mut settings = LiveSettings::clone(&self.live.borrow());
+        f(&mut settings);
+        self.live.send_replace(Arc::new(settings));
+    }
+
+    fn status(&self) -> String {
+        let settings = self.live.borrow().clone();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "paused = {}",
+            self.manual_pause.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "screen_off = {}",
+            self.screen_off.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "profile = {}",
+            settings.profile.as_deref().unwrap_or("default")
+        );
+        let _ = writeln!(out, "brightness = {}", settings.brightness);
+        let _ = writeln!(out, "smooth = {}", settings.smooth);
+        let _ = writeln!(out, "delay_ms = {}", settings.delay_ms);
+        let _ = writeln!(out, "letterbox = {}", settings.letterbox);
+        if let Some(schedule) = &settings.schedule {
+            let state = schedule.at(&Local::now());
+            let _ = writeln!(
+                out,
+                "schedule = {:.0}K, brightness x{:.2}",
+                state.temperature, state.brightness
+            );
+        }
+        for (i, fps) in self.camera_fps.iter().enumerate() {
+            let fps = f32::from_bits(fps.load(Ordering::Relaxed));
+            let _ = writeln!(out, "camera[{i}].fps = {fps:.1}");
+        }
+        for (i, sink) in self.sinks.iter().enumerate() {
+            let state = if sink.is_ok() { "ok" } else { "error" };
+            let _ = writeln!(out, "sink[{i}].{} = {state}", sink.name);
+            if let Some(Some(limit)) = settings.current_limits.get(i) {
+                let _ = writeln!(
+                    out,
+                    "sink[{i}].draw_ma = {:.0} (limit {})",
+                    sink.draw_ma(),
+                    limit.max_ma
+                );
+            }
+        }
+        out
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/ambiway.sock`, or `ambiway-<uid>/ambiway.sock` in the temp
+/// dir if it isn't set.
+///
+/// The temp dir is shared with other users, so the directory is created only
+/// accessible to us and refused if someone else got to it first.
+pub fn socket_path() -> std::io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(PathBuf::from(dir).join("ambiway.sock"));
+    }
+    // SAFETY: getuid has no preconditions and can't fail
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("ambiway-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let meta = std::fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{dir:?} is not a directory only this user can access"),
+        ));
+    }
+    Ok(dir.join("ambiway.sock"))
+}
+
+/// Binds the control socket, replacing a stale one left by a crashed daemon.
+/// Only the owner may connect.
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("{path:?} is used by another ambiway instance"),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    // The socket gets its permissions when it is created, so there is no
+    // moment in which others could connect
+    // SAFETY: umask only swaps the file mode mask of the process
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    // SAFETY: as above, restores the previous mask
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+/// Answers requests on `listener` until the daemon exits
+pub async fn run_control_task(listener: UnixListener, control: Control) {
+    let control = Arc::new(control);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("[Control] Accept error: {e}");
+                continue;
+            }
+        };
+
+        let control = control.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut line = String::new();
+            if let Err(e) = BufReader::new(read).read_line(&mut line).await {
+                eprintln!("[Control] Read error: {e}");
+                return;
+            }
+            let response = control.handle(line.trim());
+            if let Err(e) = write.write_all(response.as_bytes()).await {
+                eprintln!("[Control] Write error: {e}");
+            }
+        });
+    }
+}
+
+/// Sends `request` to the running daemon and returns its response
+pub fn send_request(request: &Request) -> Result<String, Box<dyn std::error::Error>> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| format!("Failed to connect to {path:?}, is ambiway running? ({e})"))?;
+    writeln!(stream, "{request}")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socket_is_created_owner_only() {
+        let dir = std::env::temp_dir().join(format!("ambiway-{}-control", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ambiway.sock");
+        let listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // A second daemon must not take over a live socket
+        assert_eq!(bind(&path).unwrap_err().kind(), ErrorKind::AddrInUse);
+        drop(listener);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
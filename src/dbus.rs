@@ -0,0 +1,332 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use tokio::{
+    select,
+    sync::{Notify, watch},
+};
+use zbus::{connection, fdo, interface};
+
+use crate::reload::LiveSettings;
+
+const BUS_NAME: &str = "org.timasoft.Ambiway";
+const OBJECT_PATH: &str = "/org/timasoft/Ambiway";
+
+/// Property values last announced to D-Bus clients
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Snapshot {
+    paused: bool,
+    screen_off: bool,
+    brightness: f32,
+    profile: Option<String>,
+    profiles: Vec<String>,
+}
+
+struct Ambiway {
+    manual_pause: Arc<AtomicBool>,
+    screen_off: Arc<AtomicBool>,
+    pause_changed: Arc<Notify>,
+    live: watch::Sender<Arc<LiveSettings>>,
+    seen: Snapshot,
+}
+
+impl Ambiway {
+    fn snapshot(&self) -> Snapshot {
+        let settings = self.live.borrow();
+        Snapshot {
+            paused: self.manual_pause.load(Ordering::Relaxed),
+            screen_off: self.screen_off.load(Ordering::Relaxed),
+            brightness: settings.brightness,
+            profile: settings.profile.clone(),
+            profiles: settings.profile_names(),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut LiveSettings) -> Result<(), String>) -> fdo::Result<()> {
+        let mut settings = LiveSettings::clone(&self.live.borrow());
+        f(&mut settings).map_err(fdo::Error::InvalidArgs)?;
+        self.live.send_replace(Arc::new(settings));
+        Ok(())
+    }
+}
+
+#[interface(name = "org.timasoft.Ambiway")]
+impl Ambiway {
+    /// Toggle pause
+    fn toggle(&self) {
+        let current = self.manual_pause.load(Ordering::Relaxed);
+        self.manual_pause.store(!current, Ordering::Relaxed);
+        self.pause_changed.notify_one();
+    }
+
+    /// Whether the ambilight was paused by the user
+    #[zbus(property)]
+    fn paused(&self) -> bool {
+        self.manual_pause.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    fn set_paused(&mut self, paused: bool) {
+        self.manual_pause.store(paused, Ordering::Relaxed);
+        self.seen.paused = paused;
+    }
+
+    /// Whether the ambilight is paused because the screen is off (DPMS)
+    #[zbus(property)]
+    fn screen_off(&self) -> bool {
+        self.screen_off.load(Ordering::Relaxed)
+    }
+
+    /// Brightness multiplier
+    #[zbus(property)]
+    fn brightness(&self) -> f64 {
+        self.live.borrow().brightness as f64
+    }
+
+    #[zbus(property)]
+    fn set_brightness(&mut self, brightness: f64) -> fdo::Result<()> {
+        let brightness = brightness as f32;
+        self.update(|s| {
+            if !brightness.is_finite() || brightness < 0.0 {
+                return Err(format!(
+                    "brightness must be a non-negative number, got {brightness}"
+                ));
+            }
+            s.brightness = brightness;
+            Ok(())
+        })?;
+        self.seen.brightness = brightness;
+        Ok(())
+    }
+
+    /// Active profile, `default` means plain `[settings]`
+    #[zbus(property)]
+    fn profile(&self) -> String {
+        self.live
+            .borrow()
+            .profile
+            .clone()
+            .unwrap_or_else(|| "default".to_string())
+    }
+
+    #[zbus(property)]
+    fn set_profile(&mut self, name: String) -> fdo::Result<()> {
+        let name = (name != "default").then_some(name);
+        self.update(|s| s.set_profile(name.as_deref()))?;
+        self.seen.profile = name;
+        Ok(())
+    }
+
+    /// Names of all `[profile.<name>]` tables
+    #[zbus(property)]
+    fn profiles(&self) -> Vec<String> {
+        self.live.borrow().profile_names()
+    }
+}
+
+/// Serves `org.timasoft.Ambiway` on the session bus until the daemon exits.
+///
+/// `pause_changed` is notified whenever `manual_pause` or `screen_off` change.
+pub async fn run_dbus_service(
+    manual_pause: Arc<AtomicBool>,
+    screen_off: Arc<AtomicBool>,
+    pause_changed: Arc<Notify>,
+    live: watch::Sender<Arc<LiveSettings>>,
+) -> zbus::Result<()> {
+    let iface = Ambiway {
+        manual_pause,
+        screen_off,
+        pause_changed,
+        live,
+        seen: Snapshot::default(),
+    };
+    serve(connection::Builder::session()?, iface).await
+}
+
+async fn serve(builder: connection::Builder<'_>, mut iface: Ambiway) -> zbus::Result<()> {
+    iface.seen = iface.snapshot();
+    let pause_changed = iface.pause_changed.clone();
+    let mut live_changes = iface.live.subscribe();
+
+    let conn = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, iface)?
+        .build()
+        .await?;
+    println!("[D-Bus] Serving {BUS_NAME} on the session bus");
+
+    // Pause, profile and brightness also change through signals, the control
+    // socket and reloads, so announce whatever changed when they do
+    let iface_ref = conn
+        .object_server()
+        .interface::<_, Ambiway>(OBJECT_PATH)
+        .await?;
+    loop {
+        select! {
+            _ = pause_changed.notified() => {}
+            // The interface holds a sender, so this never fails
+            _ = live_changes.changed() => {}
+        }
+
+        let (old, new) = {
+            let mut iface = iface_ref.get_mut().await;
+            let new = iface.snapshot();
+            let old = std::mem::replace(&mut iface.seen, new.clone());
+            (old, new)
+        };
+        if old == new {
+            continue;
+        }
+
+        let iface = iface_ref.get().await;
+        let emitter = iface_ref.signal_emitter();
+        if old.paused != new.paused {
+            iface.paused_changed(emitter).await?;
+        }
+        if old.screen_off != new.screen_off {
+            iface.screen_off_changed(emitter).await?;
+        }
+        if old.brightness != new.brightness {
+            iface.brightness_changed(emitter).await?;
+        }
+        if old.profile != new.profile {
+            iface.profile_changed(emitter).await?;
+        }
+        if old.profiles != new.profiles {
+            iface.profiles_changed(emitter).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use crate::{MonitorRes, config::tests::parse};
+
+    /// `dbus-daemon` with a bus of its own, stopped when dropped
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon is needed for the D-Bus tests");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[tokio::test]
+    async fn announces_changes_made_outside_the_bus() {
+        let bus = PrivateBus::start();
+        let config = parse(
+            "[settings]\n\
+             brightness = 0.5\n\
+             [[monitor]]\n\
+             cam = 0\n\
+             led = { left = 2, up = 2, right = 2, down = 2 }\n",
+        );
+        let available = [MonitorRes {
+            width: 64,
+            height: 36,
+        }];
+        let (live, _) = watch::channel(Arc::new(LiveSettings::new(&config, &available)));
+        let manual_pause = Arc::new(AtomicBool::new(false));
+        let pause_changed = Arc::new(Notify::new());
+        let iface = Ambiway {
+            manual_pause: manual_pause.clone(),
+            screen_off: Arc::new(AtomicBool::new(false)),
+            pause_changed: pause_changed.clone(),
+            live: live.clone(),
+            seen: Snapshot::default(),
+        };
+        let service = tokio::spawn(serve(
+            connection::Builder::address(bus.address.as_str()).unwrap(),
+            iface,
+        ));
+
+        let client = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        // The name is taken once the service is up
+        let dbus = fdo::DBusProxy::new(&client).await.unwrap();
+        while !dbus
+            .name_has_owner(BUS_NAME.try_into().unwrap())
+            .await
+            .unwrap()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let properties = fdo::PropertiesProxy::builder(&client)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut changes = properties.receive_properties_changed().await.unwrap();
+
+        let mut next_change = async || {
+            let signal = tokio::time::timeout(Duration::from_secs(5), changes.next())
+                .await
+                .expect("no PropertiesChanged signal")
+                .unwrap();
+            let args = signal.args().unwrap();
+            let mut changed: Vec<(String, String)> = args
+                .changed_properties()
+                .iter()
+                .map(|(name, value)| (name.to_string(), format!("{value}")))
+                .collect();
+            changed.sort();
+            changed
+        };
+
+        // Like the SIGUSR1 handler and the control socket
+        manual_pause.store(true, Ordering::Relaxed);
+        pause_changed.notify_one();
+        assert_eq!(
+            next_change().await,
+            [("Paused".to_string(), "true".to_string())]
+        );
+
+        // Like a reload or `ambiway ctl brightness`
+        let mut settings = LiveSettings::clone(&live.borrow());
+        settings.brightness = 0.25;
+        live.send_replace(Arc::new(settings));
+        assert_eq!(
+            next_change().await,
+            [("Brightness".to_string(), "0.25".to_string())]
+        );
+
+        service.abort();
+    }
+}
@@ -0,0 +1,293 @@
+use opencv::{core, prelude::*};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{arrange_strip, config::MonitorConfig, source::Frame};
+
+/// Bits kept per channel when looking for the dominant color
+const DOMINANT_BITS: u32 = 4;
+
+/// Screen edge a region lies on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Up,
+    Right,
+    Down,
+}
+
+/// How the color of a region is computed from its pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Estimator {
+    /// Average of all pixels
+    #[default]
+    Mean,
+    /// Median of each channel, small bright or dark spots don't change it
+    Median,
+    /// Average of the most common color, found with a quantized histogram
+    Dominant,
+    /// Average with pixels closer to the screen edge weighted more
+    Edge,
+}
+
+/// `estimator` of a monitor, one for all sides or a table per side
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum SideEstimators {
+    All(Estimator),
+    Sides(PerSide),
+}
+
+/// Estimators of single sides, missing ones use `settings.estimator`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PerSide {
+    pub left: Option<Estimator>,
+    pub up: Option<Estimator>,
+    pub right: Option<Estimator>,
+    pub down: Option<Estimator>,
+}
+
+impl SideEstimators {
+    fn get(&self, side: Side) -> Option<Estimator> {
+        match self {
+            SideEstimators::All(estimator) => Some(*estimator),
+            SideEstimators::Sides(sides) => match side {
+                Side::Left => sides.left,
+                Side::Up => sides.up,
+                Side::Right => sides.right,
+                Side::Down => sides.down,
+            },
+        }
+    }
+}
+
+/// Estimator and screen edge of one region
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    pub side: Side,
+    pub estimator: Estimator,
+    /// Multiplier of the LED's brightness
+    pub weight: f32,
+}
+
+impl Sampler {
+    /// Samplers of a monitor's regions, in the order `calculate_regions` returns them.
+    /// Corner LEDs use the side before them clockwise, LEDs of a layout file
+    /// the edge closest to them.
+    pub fn for_monitor(cfg: &MonitorConfig, default: Estimator) -> Vec<Sampler> {
+        let estimator = |side| cfg.estimator.and_then(|e| e.get(side)).unwrap_or(default);
+        let Some(led) = cfg.sides() else {
+            return cfg
+                .layout_leds
+                .iter()
+                .map(|l| Sampler {
+                    side: l.side(),
+                    estimator: estimator(l.side()),
+                    weight: l.weight,
+                })
+                .collect();
+        };
+        let mut samplers: Vec<Sampler> = [
+            (Side::Left, led.left),
+            (Side::Left, led.corner.up_left),
+            (Side::Up, led.up),
+            (Side::Up, led.corner.up_right),
+            (Side::Right, led.right),
+            (Side::Right, led.corner.down_right),
+            (Side::Down, led.down),
+            (Side::Down, led.corner.down_left),
+        ]
+        .into_iter()
+        .flat_map(|(side, count)| {
+            let sampler = Sampler {
+                side,
+                estimator: estimator(side),
+                weight: 1.0,
+            };
+            std::iter::repeat_n(sampler, count.max(0) as usize)
+        })
+        .collect();
+        arrange_strip(&mut samplers, cfg);
+        samplers
+    }
+
+    /// Color of `region` on `frame` as `[r, g, b]`
+    pub fn estimate(
+        &self,
+        frame: &Frame,
+        region: &[i32; 4],
+    ) -> Result<[f32; 3], Box<dyn std::error::Error>> {
+        let [x1, y1, x2, y2] = *region;
+        if x1 < 0 || y1 < 0 || x2 > frame.width || y2 > frame.height || x1 >= x2 || y1 >= y2 {
+            return Err(format!(
+                "Region {region:?} is outside of {}x{} frame",
+                frame.width, frame.height
+            )
+            .into());
+        }
+
+        let data = frame.pixels();
+        let pixels = (y1..y2).flat_map(|y| (x1..x2).map(move |x| (x, y)));
+        Ok(match self.estimator {
+            Estimator::Mean => {
+                let roi = Mat::roi(&frame.mat, core::Rect::new(x1, y1, x2 - x1, y2 - y1))?;
+                // mean returns Scalar(B, G, R, A)
+                let mean = core::mean(&roi, &core::no_array())?;
+                [mean[2] as f32, mean[1] as f32, mean[0] as f32]
+            }
+            Estimator::Median => median(pixels.map(|(x, y)| data.rgb_at(x, y))),
+            Estimator::Dominant => dominant(pixels.map(|(x, y)| data.rgb_at(x, y))),
+            Estimator::Edge => {
+                // The outermost row or column gets the full depth as weight, the innermost 1
+                let weight = |x: i32, y: i32| match self.side {
+                    Side::Left => x2 - x,
+                    Side::Up => y2 - y,
+                    Side::Right => x - x1 + 1,
+                    Side::Down => y - y1 + 1,
+                };
+                weighted_mean(pixels.map(|(x, y)| (data.rgb_at(x, y), weight(x, y) as u64)))
+            }
+        })
+    }
+}
+
+fn weighted_mean(pixels: impl Iterator<Item = ([u8; 3], u64)>) -> [f32; 3] {
+    let mut sum = [0u64; 3];
+    let mut total = 0u64;
+    for (px, weight) in pixels {
+        for (s, &c) in sum.iter_mut().zip(&px) {
+            *s += c as u64 * weight;
+        }
+        total += weight;
+    }
+    sum.map(|s| s as f32 / total.max(1) as f32)
+}
+
+fn median(pixels: impl Iterator<Item = [u8; 3]>) -> [f32; 3] {
+    let mut histograms = [[0u32; 256]; 3];
+    let mut count = 0u32;
+    for px in pixels {
+        for (histogram, &c) in histograms.iter_mut().zip(&px) {
+            histogram[c as usize] += 1;
+        }
+        count += 1;
+    }
+
+    let half = count.div_ceil(2);
+    histograms.map(|histogram| {
+        let mut seen = 0;
+        histogram
+            .iter()
+            .position(|&n| {
+                seen += n;
+                seen >= half
+            })
+            .unwrap_or(0) as f32
+    })
+}
+
+fn dominant(pixels: impl Iterator<Item = [u8; 3]>) -> [f32; 3] {
+    let shift = 8 - DOMINANT_BITS;
+    let mut bins: HashMap<u32, ([u64; 3], u64)> = HashMap::new();
+    for px in pixels {
+        let key = px
+            .iter()
+            .fold(0, |key, &c| (key << DOMINANT_BITS) | (c >> shift) as u32);
+        let (sum, count) = bins.entry(key).or_default();
+        for (s, &c) in sum.iter_mut().zip(&px) {
+            *s += c as u64;
+        }
+        *count += 1;
+    }
+
+    // Ties go to the higher bin so the result doesn't depend on map order
+    bins.into_iter()
+        .max_by_key(|&(key, (_, count))| (count, key))
+        .map(|(_, (sum, count))| sum.map(|s| s as f32 / count as f32))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::tests::frame;
+
+    fn estimate(side: Side, estimator: Estimator, frame: &Frame, region: [i32; 4]) -> [f32; 3] {
+        let sampler = Sampler {
+            side,
+            estimator,
+            weight: 1.0,
+        };
+        sampler.estimate(frame, &region).unwrap()
+    }
+
+    #[test]
+    fn every_estimator_keeps_a_uniform_color() {
+        let frame = frame(8, 6, |_, _| [10, 120, 240]);
+        for estimator in [
+            Estimator::Mean,
+            Estimator::Median,
+            Estimator::Dominant,
+            Estimator::Edge,
+        ] {
+            assert_eq!(
+                estimate(Side::Up, estimator, &frame, [1, 1, 7, 4]),
+                [10.0, 120.0, 240.0],
+                "{estimator:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn median_ignores_outliers() {
+        // 12 of 25 pixels are white subtitles on a dark picture
+        let frame = frame(5, 5, |x, y| {
+            if (x + y * 5) % 2 == 1 {
+                [255; 3]
+            } else {
+                [20, 30, 40]
+            }
+        });
+        let region = [0, 0, 5, 5];
+        assert_eq!(
+            estimate(Side::Down, Estimator::Median, &frame, region),
+            [20.0, 30.0, 40.0]
+        );
+        assert!(estimate(Side::Down, Estimator::Mean, &frame, region)[0] > 100.0);
+    }
+
+    #[test]
+    fn dominant_averages_the_most_common_color() {
+        // Two shades of red in one histogram bin outnumber the blue
+        let frame = frame(4, 4, |x, y| match (x + y * 4) % 8 {
+            0..=2 => [200, 10, 10],
+            3..=4 => [202, 12, 14],
+            _ => [10, 10, 200],
+        });
+        let [r, g, b] = estimate(Side::Left, Estimator::Dominant, &frame, [0, 0, 4, 4]);
+        assert_eq!([r, g, b].map(|c| c.round()), [201.0, 11.0, 12.0]);
+    }
+
+    #[test]
+    fn edge_weights_pixels_near_the_screen_edge() {
+        // Columns get brighter towards the middle of the screen
+        let frame = frame(4, 2, |x, _| [x as u8 * 60; 3]);
+        let region = [0, 0, 4, 2];
+        assert_eq!(
+            estimate(Side::Left, Estimator::Mean, &frame, region)[0],
+            90.0
+        );
+        // Weights 4, 3, 2, 1 from the left edge inward
+        assert_eq!(
+            estimate(Side::Left, Estimator::Edge, &frame, region)[0],
+            60.0
+        );
+        // Weights 1, 2, 3, 4 towards the right edge
+        assert_eq!(
+            estimate(Side::Right, Estimator::Edge, &frame, region)[0],
+            120.0
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{MonitorRes, estimate::Side};
+
+/// Layout file with an explicit region per LED, in strip order
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub led: Vec<LayoutLed>,
+}
+
+/// One `[[led]]` entry of a layout file
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayoutLed {
+    /// `[x1, y1, x2, y2]` as fractions of the monitor width and height
+    pub rect: [f32; 4],
+    /// Multiplier of the LED's brightness
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+fn is_default_weight(weight: &f32) -> bool {
+    *weight == 1.0
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+impl Layout {
+    /// Reads a layout from TOML, or JSON if `path` ends in `.json`.
+    ///
+    /// JSON files can also be Hyperion or HyperHDR layouts or configs, and
+    /// `.ini` files Prismatik profiles. Comments in JSON files are ignored,
+    /// classic Hyperion configs usually have them.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if has_extension(path, "ini") {
+            prismatik_layout(&text)
+        } else if has_extension(path, "json") {
+            let value: serde_json::Value =
+                serde_json::from_str(&strip_json_comments(&text)).map_err(|e| e.to_string())?;
+            if value.get("led").is_some() {
+                serde_json::from_value(value).map_err(|e| e.to_string())
+            } else {
+                hyperion_layout(value)
+            }
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Writes the layout as TOML, or JSON if `path` ends in `.json`
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let text = if has_extension(path, "json") {
+            serde_json::to_string_pretty(self)?
+        } else {
+            toml::to_string(self)?
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Layout of `regions` in pixels on a `res` monitor
+    pub fn from_regions(regions: &[[i32; 4]], res: MonitorRes) -> Self {
+        let fraction = |v: i32, total: i32| (v as f32 / total as f32 * 10000.0).round() / 10000.0;
+        Self {
+            led: regions
+                .iter()
+                .map(|&[x1, y1, x2, y2]| LayoutLed {
+                    rect: [
+                        fraction(x1, res.width),
+                        fraction(y1, res.height),
+                        fraction(x2, res.width),
+                        fraction(y2, res.height),
+                    ],
+                    weight: 1.0,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl LayoutLed {
+    pub fn is_valid(&self) -> bool {
+        let [x1, y1, x2, y2] = self.rect;
+        (0.0..=1.0).contains(&x1)
+            && (0.0..=1.0).contains(&y1)
+            && x2 <= 1.0
+            && y2 <= 1.0
+            && x1 < x2
+            && y1 < y2
+    }
+
+    /// Region in pixels on a `res` monitor, at least one pixel large
+    pub fn region(&self, res: MonitorRes) -> [i32; 4] {
+        let [x1, y1, x2, y2] = self.rect;
+        let x1 = ((x1 * res.width as f32).round() as i32).min(res.width - 1);
+        let y1 = ((y1 * res.height as f32).round() as i32).min(res.height - 1);
+        let x2 = ((x2 * res.width as f32).round() as i32).max(x1 + 1);
+        let y2 = ((y2 * res.height as f32).round() as i32).max(y1 + 1);
+        [x1, y1, x2, y2]
+    }
+
+    /// Screen edge closest to the middle of the LED's rect
+    pub fn side(&self) -> Side {
+        let [x1, y1, x2, y2] = self.rect;
+        let (x, y) = ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+        [
+            (x, Side::Left),
+            (y, Side::Up),
+            (1.0 - x, Side::Right),
+            (1.0 - y, Side::Down),
+        ]
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, side)| side)
+        .unwrap_or(Side::Left)
+    }
+}
+
+/// LED of a Hyperion or HyperHDR layout
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HyperionLed {
+    /// Hyperion.ng and HyperHDR
+    Bounds {
+        hmin: f32,
+        hmax: f32,
+        vmin: f32,
+        vmax: f32,
+    },
+    /// Classic Hyperion
+    Scan {
+        hscan: HyperionScan,
+        vscan: HyperionScan,
+    },
+}
+
+/// Entry of Hyperion's `leds`, classic configs number them with `index`
+#[derive(Deserialize)]
+struct HyperionEntry {
+    index: Option<usize>,
+    #[serde(flatten)]
+    led: HyperionLed,
+}
+
+#[derive(Deserialize)]
+struct HyperionScan {
+    minimum: f32,
+    maximum: f32,
+}
+
+/// Exported LED layout, or a whole config with its `leds`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HyperionFile {
+    Layout(Vec<HyperionEntry>),
+    Config { leds: Vec<HyperionEntry> },
+}
+
+fn hyperion_layout(value: serde_json::Value) -> Result<Layout, String> {
+    let mut leds = match serde_json::from_value(value) {
+        Ok(HyperionFile::Layout(leds)) | Ok(HyperionFile::Config { leds }) => leds,
+        Err(_) => {
+            return Err(
+                "expected `led` entries, or Hyperion `leds` with hmin/hmax/vmin/vmax or hscan/vscan"
+                    .to_string(),
+            );
+        }
+    };
+    // The strip order is given by `index` if every LED has one, else by the file order
+    if leds.iter().all(|l| l.index.is_some()) {
+        leds.sort_by_key(|l| l.index);
+    }
+
+    Ok(Layout {
+        led: leds
+            .into_iter()
+            .map(|entry| {
+                let rect = match entry.led {
+                    HyperionLed::Bounds {
+                        hmin,
+                        hmax,
+                        vmin,
+                        vmax,
+                    } => [hmin, vmin, hmax, vmax],
+                    HyperionLed::Scan { hscan, vscan } => {
+                        [hscan.minimum, vscan.minimum, hscan.maximum, vscan.maximum]
+                    }
+                };
+                LayoutLed { rect, weight: 1.0 }
+            })
+            .collect(),
+    })
+}
+
+/// Grab area of a Prismatik profile, in desktop pixels
+#[derive(Default)]
+struct PrismatikArea {
+    position: Option<[f32; 2]>,
+    size: Option<[f32; 2]>,
+    disabled: bool,
+}
+
+/// Reads the `[LED_n]` sections of a Prismatik profile.
+///
+/// Prismatik places the areas on the whole desktop and the profile doesn't
+/// store the screen size, so they are scaled to the box all of them cover.
+/// That box is only the monitor if LEDs sit along every edge, profiles
+/// without some edge are rejected instead of being stretched.
+/// Disabled LEDs stay in the strip with a weight of 0.
+fn prismatik_layout(text: &str) -> Result<Layout, String> {
+    let mut areas: BTreeMap<usize, PrismatikArea> = BTreeMap::new();
+    let mut current = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = section
+                .strip_prefix("LED_")
+                .and_then(|n| n.parse::<usize>().ok());
+            continue;
+        }
+        let (Some(n), Some((key, value))) = (current, line.split_once('=')) else {
+            continue;
+        };
+        let area = areas.entry(n).or_default();
+        match key.trim() {
+            "Position" => area.position = qt_pair(value, "@Point"),
+            "Size" => area.size = qt_pair(value, "@Size"),
+            "IsEnabled" => area.disabled = value.trim() == "false",
+            _ => {}
+        }
+    }
+    if areas.is_empty() {
+        return Err("no [LED_n] sections found in the Prismatik profile".to_string());
+    }
+
+    let mut rects = Vec::with_capacity(areas.len());
+    for (n, area) in &areas {
+        let (Some([x, y]), Some([w, h])) = (area.position, area.size) else {
+            return Err(format!("LED_{n} has no Position or Size"));
+        };
+        rects.push(([x, y, x + w, y + h], area.disabled));
+    }
+    let min_x = rects.iter().map(|(r, _)| r[0]).fold(f32::MAX, f32::min);
+    let min_y = rects.iter().map(|(r, _)| r[1]).fold(f32::MAX, f32::min);
+    let width = rects.iter().map(|(r, _)| r[2]).fold(f32::MIN, f32::max) - min_x;
+    let height = rects.iter().map(|(r, _)| r[3]).fold(f32::MIN, f32::max) - min_y;
+    if width <= 0.0 || height <= 0.0 {
+        return Err("the Prismatik areas cover no space".to_string());
+    }
+
+    let layout = Layout {
+        led: rects
+            .into_iter()
+            .map(|([x1, y1, x2, y2], disabled)| LayoutLed {
+                rect: [
+                    (x1 - min_x) / width,
+                    (y1 - min_y) / height,
+                    (x2 - min_x) / width,
+                    (y2 - min_y) / height,
+                ],
+                weight: if disabled { 0.0 } else { 1.0 },
+            })
+            .collect(),
+    };
+    let missing: Vec<&str> = [
+        (Side::Left, "left"),
+        (Side::Up, "top"),
+        (Side::Right, "right"),
+        (Side::Down, "bottom"),
+    ]
+    .into_iter()
+    .filter(|(side, _)| !layout.led.iter().any(|l| l.side() == *side))
+    .map(|(_, name)| name)
+    .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "the Prismatik areas have no LEDs along the {} edge of the screen, \
+             so the screen size can't be told from them",
+            missing.join(", ")
+        ));
+    }
+    Ok(layout)
+}
+
+/// Replaces `//` and `/* */` comments outside of strings with spaces,
+/// keeping line breaks so parse errors point at the right line
+fn strip_json_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    out.push(if c == '\n' { '\n' } else { ' ' });
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parses a Qt value such as `@Point(10 20)`
+fn qt_pair(value: &str, kind: &str) -> Option<[f32; 2]> {
+    let inner = value
+        .trim()
+        .strip_prefix(kind)?
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    let mut numbers = inner.split_whitespace().map(|v| v.parse::<f32>().ok());
+    Some([numbers.next()??, numbers.next()??])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_regions, config::load_config_from_file};
+
+    /// Empty directory for the files of one test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ambiway-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fixture(name: &str) -> Layout {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        Layout::load(&path).unwrap()
+    }
+
+    fn rects(layout: &Layout) -> Vec<[f32; 4]> {
+        layout.led.iter().map(|l| l.rect).collect()
+    }
+
+    #[test]
+    fn layout_from_sides_loads_back_to_the_same_regions() {
+        let dir = temp_dir("round-trip");
+        let res = MonitorRes {
+            width: 1920,
+            height: 1080,
+        };
+        let settings = "[settings]\nsize = 60\n";
+        let monitor = "[[monitor]]\n\
+                       cam = 0\n\
+                       led = { left = 12, up = 21, right = 13, down = 20, corner = { up_left = 1, down_right = 2 } }\n\
+                       start_corner = \"up_right\"\n\
+                       start_offset = 3\n\
+                       direction = \"ccw\"\n\
+                       indent = { left_up = 40, up_right = 25, down_left = 7 }\n";
+        fs::write(dir.join("sides.toml"), format!("{settings}{monitor}")).unwrap();
+        let sides = load_config_from_file(&dir.join("sides.toml")).unwrap();
+        let regions = calculate_regions(&[res], &sides.monitors, 60).remove(0);
+
+        for file in ["layout.toml", "layout.json"] {
+            Layout::from_regions(&regions, res)
+                .save(&dir.join(file))
+                .unwrap();
+            let config = format!("{settings}[[monitor]]\ncam = 0\nlayout = \"{file}\"\n");
+            fs::write(dir.join("config.toml"), config).unwrap();
+            let loaded = load_config_from_file(&dir.join("config.toml")).unwrap();
+            assert_eq!(
+                calculate_regions(&[res], &loaded.monitors, 60).remove(0),
+                regions,
+                "{file}"
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hyperion_ng_layout_keeps_its_bounds() {
+        let layout = fixture("hyperion-ng-layout.json");
+        assert_eq!(layout.led.len(), 8);
+        assert_eq!(layout.led[0].rect, [0.0, 0.0, 0.5, 0.08]);
+        assert_eq!(layout.led[3].rect, [0.95, 0.5, 1.0, 1.0]);
+        let sides: Vec<Side> = layout.led.iter().map(LayoutLed::side).collect();
+        assert_eq!(
+            sides,
+            [
+                Side::Up,
+                Side::Up,
+                Side::Right,
+                Side::Right,
+                Side::Down,
+                Side::Down,
+                Side::Left,
+                Side::Left,
+            ]
+        );
+    }
+
+    #[test]
+    fn classic_hyperion_config_with_comments_is_ordered_by_index() {
+        let layout = fixture("hyperion-classic.config.json");
+        assert_eq!(
+            rects(&layout),
+            [
+                [0.0, 0.0, 0.5, 0.08],
+                [0.5, 0.0, 1.0, 0.08],
+                [0.0, 0.92, 0.5, 1.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_inside_strings_are_kept() {
+        let text = "{ \"url\": \"http://a/*b*/\", // note\n \"n\": /* one */ 1 }";
+        let value: serde_json::Value = serde_json::from_str(&strip_json_comments(text)).unwrap();
+        assert_eq!(value["url"], "http://a/*b*/");
+        assert_eq!(value["n"], 1);
+    }
+
+    #[test]
+    fn prismatik_profile_is_scaled_to_the_screen() {
+        let layout = fixture("prismatik-profile.ini");
+        let res = MonitorRes {
+            width: 1920,
+            height: 1080,
+        };
+        let regions: Vec<[i32; 4]> = layout.led.iter().map(|l| l.region(res)).collect();
+        assert_eq!(
+            regions,
+            [
+                [0, 0, 960, 150],
+                [960, 0, 1920, 150],
+                [1770, 150, 1920, 930],
+                [960, 930, 1920, 1080],
+                [0, 930, 960, 1080],
+                [0, 150, 150, 930],
+            ]
+        );
+        let weights: Vec<f32> = layout.led.iter().map(|l| l.weight).collect();
+        assert_eq!(weights, [1.0, 1.0, 1.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn prismatik_profile_without_every_edge_is_rejected() {
+        // Two vertical strips, the bounding box would stretch them over the screen
+        let profile = "[LED_1]\nPosition=@Point(0 100)\nSize=@Size(150 400)\n\
+                       [LED_2]\nPosition=@Point(1770 100)\nSize=@Size(150 400)\n";
+        let error = prismatik_layout(profile).unwrap_err();
+        assert!(error.contains("top, bottom"), "{error}");
+    }
+}
@@ -0,0 +1,319 @@
+use serde::Deserialize;
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::source::Frame;
+
+/// Brightest channel value still counted as black, leaves room for noise
+const BLACK_THRESHOLD: u8 = 20;
+/// Every n-th pixel of a row or column is checked
+const SCAN_STEP: usize = 4;
+/// Bars can cover at most this share of each side, so dark scenes aren't cut
+const MAX_BAR_SHARE: f32 = 0.25;
+/// Bars differing by at most this many pixels count as the same
+const BAR_TOLERANCE: i32 = 2;
+/// How long new bars must stay before the regions follow them
+const LETTERBOX_HOLD: Duration = Duration::from_secs(2);
+
+/// Black bars around the picture, in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Bars {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+impl Bars {
+    fn is_valid(&self) -> bool {
+        [self.top, self.bottom, self.left, self.right]
+            .iter()
+            .all(|&b| b >= 0)
+    }
+
+    fn is_close(&self, other: &Bars) -> bool {
+        (self.top - other.top).abs() <= BAR_TOLERANCE
+            && (self.bottom - other.bottom).abs() <= BAR_TOLERANCE
+            && (self.left - other.left).abs() <= BAR_TOLERANCE
+            && (self.right - other.right).abs() <= BAR_TOLERANCE
+    }
+}
+
+/// How the sampling regions deal with black bars
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawLetterbox")]
+pub enum Letterbox {
+    /// Regions stay at the monitor edges
+    #[default]
+    Off,
+    /// Bars are detected on every frame
+    Auto,
+    /// Bars of a fixed size
+    Fixed(Bars),
+}
+
+/// `letterbox` as written in the config, a string or a table of bars
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLetterbox {
+    Text(String),
+    Bars(Bars),
+}
+
+impl TryFrom<RawLetterbox> for Letterbox {
+    type Error = String;
+
+    fn try_from(raw: RawLetterbox) -> Result<Self, Self::Error> {
+        match raw {
+            RawLetterbox::Text(text) => text.parse(),
+            RawLetterbox::Bars(bars) => Letterbox::fixed(bars),
+        }
+    }
+}
+
+impl Letterbox {
+    fn fixed(bars: Bars) -> Result<Self, String> {
+        if !bars.is_valid() {
+            return Err(format!(
+                "letterbox bars must not be negative, got {}",
+                Letterbox::Fixed(bars)
+            ));
+        }
+        Ok(Letterbox::Fixed(bars))
+    }
+}
+
+impl FromStr for Letterbox {
+    type Err = String;
+
+    /// `auto`, `off` or `top,bottom[,left,right]` in pixels
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => return Ok(Letterbox::Auto),
+            "off" => return Ok(Letterbox::Off),
+            _ => {}
+        }
+
+        let sizes = s
+            .split(',')
+            .map(|v| v.trim().parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid letterbox {s}, expected auto, off or top,bottom"))?;
+        let bars = match sizes[..] {
+            [top, bottom] => Bars {
+                top,
+                bottom,
+                ..Bars::default()
+            },
+            [top, bottom, left, right] => Bars {
+                top,
+                bottom,
+                left,
+                right,
+            },
+            _ => {
+                return Err(format!(
+                    "invalid letterbox {s}, expected top,bottom or top,bottom,left,right"
+                ));
+            }
+        };
+        Letterbox::fixed(bars)
+    }
+}
+
+impl Display for Letterbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Letterbox::Off => write!(f, "off"),
+            Letterbox::Auto => write!(f, "auto"),
+            Letterbox::Fixed(b) => write!(f, "{},{},{},{}", b.top, b.bottom, b.left, b.right),
+        }
+    }
+}
+
+/// Finds black bars on `frame`, `None` if it is black all over.
+///
+/// Letterboxed and pillarboxed content is centered, so opposite bars are
+/// given the size of the thinner one. This also keeps subtitles inside a bar
+/// from being cut off.
+pub fn detect_bars(frame: &Frame) -> Option<Bars> {
+    let (width, height) = (frame.width, frame.height);
+    let pixels = frame.pixels();
+    let row_black = |y: i32| {
+        (0..width)
+            .step_by(SCAN_STEP)
+            .all(|x| is_black(pixels.rgb_at(x, y)))
+    };
+    let column_black = |x: i32| {
+        (0..height)
+            .step_by(SCAN_STEP)
+            .all(|y| is_black(pixels.rgb_at(x, y)))
+    };
+
+    let top = (0..height).take_while(|&y| row_black(y)).count() as i32;
+    if top == height {
+        return None;
+    }
+    let bottom = (0..height).rev().take_while(|&y| row_black(y)).count() as i32;
+    let left = (0..width).take_while(|&x| column_black(x)).count() as i32;
+    let right = (0..width).rev().take_while(|&x| column_black(x)).count() as i32;
+
+    let max_rows = (height as f32 * MAX_BAR_SHARE) as i32;
+    let max_columns = (width as f32 * MAX_BAR_SHARE) as i32;
+    let rows = top.min(bottom).min(max_rows);
+    let columns = left.min(right).min(max_columns);
+    Some(Bars {
+        top: rows,
+        bottom: rows,
+        left: columns,
+        right: columns,
+    })
+}
+
+fn is_black(rgb: [u8; 3]) -> bool {
+    rgb.iter().all(|&c| c <= BLACK_THRESHOLD)
+}
+
+/// Follows detected bars only once they stayed the same for a while
+#[derive(Debug)]
+pub struct BarTracker {
+    current: Bars,
+    candidate: Bars,
+    since: Instant,
+}
+
+impl BarTracker {
+    /// Tracker without bars, started at `now`
+    pub fn new(now: Instant) -> Self {
+        Self {
+            current: Bars::default(),
+            candidate: Bars::default(),
+            since: now,
+        }
+    }
+
+    /// Feeds the bars found on a frame captured at `now` and returns the bars
+    /// to apply. Black frames, as `None`, keep the current bars.
+    pub fn update(&mut self, detected: Option<Bars>, now: Instant) -> Bars {
+        let Some(detected) = detected else {
+            return self.current;
+        };
+
+        if detected.is_close(&self.current) {
+            self.candidate = self.current;
+        } else if !detected.is_close(&self.candidate) {
+            self.candidate = detected;
+            self.since = now;
+        } else if now.duration_since(self.since) >= LETTERBOX_HOLD {
+            self.current = self.candidate;
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::tests::frame;
+
+    const PICTURE: [u8; 3] = [90, 140, 200];
+
+    fn bars(top: i32, bottom: i32, left: i32, right: i32) -> Bars {
+        Bars {
+            top,
+            bottom,
+            left,
+            right,
+        }
+    }
+
+    #[test]
+    fn letterbox_and_pillarbox_bars_are_found() {
+        let letterbox = frame(80, 60, |_, y| {
+            if (9..54).contains(&y) {
+                PICTURE
+            } else {
+                [0; 3]
+            }
+        });
+        // The 6 row bottom bar limits the 9 row top bar
+        assert_eq!(detect_bars(&letterbox), Some(bars(6, 6, 0, 0)));
+
+        let pillarbox = frame(80, 60, |x, _| {
+            if (12..68).contains(&x) {
+                PICTURE
+            } else {
+                [10, 12, 8]
+            }
+        });
+        assert_eq!(detect_bars(&pillarbox), Some(bars(0, 0, 12, 12)));
+    }
+
+    #[test]
+    fn full_picture_has_no_bars() {
+        let frame = frame(80, 60, |x, y| [x as u8 * 3, y as u8 * 4, 120]);
+        assert_eq!(detect_bars(&frame), Some(Bars::default()));
+    }
+
+    #[test]
+    fn dark_scenes_are_not_taken_for_bars() {
+        let black = frame(80, 60, |_, _| [0; 3]);
+        assert_eq!(detect_bars(&black), None);
+
+        // A small bright spot in a dark scene leaves at most a quarter per side
+        let dark = frame(80, 60, |x, y| {
+            if (36..44).contains(&x) && (28..32).contains(&y) {
+                [255; 3]
+            } else {
+                [5; 3]
+            }
+        });
+        assert_eq!(detect_bars(&dark), Some(bars(15, 15, 20, 20)));
+
+        // Black frames keep the bars being applied
+        let start = Instant::now();
+        let mut tracker = BarTracker::new(start);
+        tracker.update(Some(bars(40, 40, 0, 0)), start);
+        let held = start + LETTERBOX_HOLD;
+        assert_eq!(
+            tracker.update(Some(bars(40, 40, 0, 0)), held),
+            bars(40, 40, 0, 0)
+        );
+        assert_eq!(
+            tracker.update(detect_bars(&black), held),
+            bars(40, 40, 0, 0)
+        );
+    }
+
+    #[test]
+    fn bars_are_followed_after_they_held() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let movie = bars(40, 40, 0, 0);
+        let mut tracker = BarTracker::new(start);
+
+        assert_eq!(tracker.update(Some(movie), at(0)), Bars::default());
+        assert_eq!(tracker.update(Some(movie), at(1999)), Bars::default());
+        // A pixel of noise still counts as the same bars
+        assert_eq!(tracker.update(Some(bars(41, 41, 0, 0)), at(2000)), movie);
+        assert_eq!(tracker.update(Some(bars(38, 38, 0, 0)), at(2100)), movie);
+    }
+
+    #[test]
+    fn flickering_bars_restart_the_hold() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let movie = bars(40, 40, 0, 0);
+        let mut tracker = BarTracker::new(start);
+
+        tracker.update(Some(movie), at(0));
+        tracker.update(Some(bars(10, 10, 0, 0)), at(1500));
+        assert_eq!(tracker.update(Some(movie), at(2500)), Bars::default());
+        assert_eq!(tracker.update(Some(movie), at(4400)), Bars::default());
+        assert_eq!(tracker.update(Some(movie), at(4500)), movie);
+    }
+}
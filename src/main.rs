@@ -1,34 +1,56 @@
-use clap::Parser;
-use directories::ProjectDirs;
-use opencv::{
-    core,
-    prelude::*,
-    videoio::{self, VideoCapture},
+mod backoff;
+mod color;
+mod config;
+mod control;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod estimate;
+mod layout;
+mod letterbox;
+mod reload;
+mod schedule;
+mod sink;
+mod source;
+
+use backoff::Backoff;
+use clap::{Parser, Subcommand};
+use color::{ColorAdjustment, Smoother};
+use config::{
+    ConfigError, Corner, Direction, DmxProtocol, MonitorConfig, OutputKind, get_config_path,
+    load_config_from_file,
 };
-use openrgb2::{OpenRgbClient, Zone};
+use control::{Control, Request, run_control_task, send_request, socket_path};
+use estimate::Sampler;
+use layout::Layout;
+use letterbox::{BarTracker, Bars, Letterbox, detect_bars};
+use reload::{LiveSettings, run_reload_task};
 use rgb::RGB8;
-use serde::Deserialize;
+use sink::{
+    DdpSink, DmxSink, HyperionSink, OpenRgbSink, SerialSink, SinkInputs, WledSink, ZoneSlot,
+    spawn_sink,
+};
+use source::{Frame, FrameSource, V4l2Source};
 use std::{
-    fmt::Display,
-    fs,
     path::PathBuf,
     sync::{
         Arc,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU32, Ordering},
     },
     time,
 };
 use tokio::{
-    io::AsyncWriteExt,
     runtime::Builder,
     select,
     signal::unix::{SignalKind, signal},
-    sync::Mutex,
+    sync::{Mutex, Notify, watch},
 };
-use tokio_serial::SerialStream;
 use xrandr::XHandle;
 
 const SHUTDOWN_BLACK_REPEATS: u32 = 5;
+/// How long a source may go without a new frame before it is reopened
+const SOURCE_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+/// How often the schedule's brightness and color temperature are recomputed
+const SCHEDULE_INTERVAL: time::Duration = time::Duration::from_secs(1);
 
 /// Ambilight with OpenRGB
 #[derive(Parser, Debug)]
@@ -41,288 +63,131 @@ struct Args {
     /// Start in paused state
     #[arg(short = 'p', long = "paused")]
     paused: bool,
-}
-
-#[derive(Debug, Deserialize)]
-struct Config {
-    led: Led,
-    indent: Indent,
-    settings: Settings,
-    serial: Option<SerialConfig>,
-}
-
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum Protocol {
-    #[default]
-    Awa,
-    Adalight,
-}
-
-impl Protocol {
-    fn header(self) -> &'static [u8; 3] {
-        match self {
-            Protocol::Awa => b"Awa",
-            Protocol::Adalight => b"Ada",
-        }
-    }
-}
 
-impl Display for Protocol {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Protocol::Awa => write!(f, "awa"),
-            Protocol::Adalight => write!(f, "adalight"),
-        }
-    }
-}
-
-#[derive(Debug, Deserialize)]
-struct SerialConfig {
-    port: String,
-    #[serde(default = "default_serial_baud")]
-    baud_rate: u32,
-    #[serde(default)]
-    protocol: Protocol,
-}
+    /// Validate the config file and exit
+    #[arg(long = "check-config")]
+    check_config: bool,
 
-fn default_serial_baud() -> u32 {
-    2_000_000
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Led {
-    left: Vec<i32>,
-    up: Vec<i32>,
-    right: Vec<i32>,
-    down: Vec<i32>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Indent {
-    left_up: Vec<i32>,
-    left_down: Vec<i32>,
-    up_left: Vec<i32>,
-    up_right: Vec<i32>,
-    right_up: Vec<i32>,
-    right_down: Vec<i32>,
-    down_left: Vec<i32>,
-    down_right: Vec<i32>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Settings {
-    #[serde(default = "default_size")]
-    size: i32,
-    #[serde(default = "default_brightness")]
-    brightness: f32,
-    #[serde(default = "default_delay_ms")]
-    delay_ms: u64,
-    #[serde(default = "default_smooth")]
-    smooth: bool,
-    cams: Vec<i32>,
-    device_id: usize,
-    zone_id_list: Vec<usize>,
-    monitor_id_list: Option<Vec<usize>>,
-}
-
-fn default_size() -> i32 {
-    50
-}
-
-fn default_brightness() -> f32 {
-    1.0
-}
-
-fn default_delay_ms() -> u64 {
-    95
-}
-
-fn default_smooth() -> bool {
-    true
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Control the running daemon
+    Ctl {
+        #[command(subcommand)]
+        request: Request,
+    },
+    /// Write the regions of a monitor to a layout file, JSON if it ends in .json
+    Layout {
+        /// Position of the monitor in the config
+        monitor: usize,
+        file: PathBuf,
+    },
 }
 
 pub type Color = RGB8;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 struct MonitorRes {
     width: i32,
     height: i32,
 }
 
-fn get_monitors_info(
-    monitor_id_list: Option<Vec<usize>>,
-) -> Result<Vec<MonitorRes>, Box<dyn std::error::Error>> {
+fn get_monitors_info() -> Result<Vec<MonitorRes>, Box<dyn std::error::Error>> {
     // Create an XHandle instance
     let mut xh = XHandle::open()?;
     // Get a list of monitors
     let monitors = xh.monitors()?;
 
-    // Filter and collect the results based on monitor_id_list
-    let info = match monitor_id_list {
-        None => {
-            // Return all monitors if no filter specified
-            monitors
-                .iter()
-                .map(|m| MonitorRes {
-                    width: m.width_px,
-                    height: m.height_px,
-                })
-                .collect()
-        }
-        Some(ids) => {
-            // Return only monitors with specified indices
-            ids.into_iter()
-                .filter_map(|i| {
-                    monitors.get(i).map(|m| MonitorRes {
-                        width: m.width_px,
-                        height: m.height_px,
-                    })
-                })
-                .collect()
-        }
-    };
-    Ok(info)
-}
-
-fn load_config() -> Config {
-    let config_path = get_config_path().expect("Failed to get config path");
-    let config_str = fs::read_to_string(&config_path)
-        .unwrap_or_else(|_| panic!("Failed to read config file: {config_path:?}"));
-    toml::from_str(&config_str).expect("Failed to parse config TOML")
-}
-
-fn load_config_from_file(path: &PathBuf) -> Config {
-    let config_str =
-        fs::read_to_string(path).unwrap_or_else(|_| panic!("Failed to read config file: {path:?}"));
-    toml::from_str(&config_str).expect("Failed to parse config TOML")
-}
-
-fn get_config_path() -> Option<PathBuf> {
-    let proj_dirs = ProjectDirs::from("com", "timasoft", "ambiway")?;
-    Some(proj_dirs.config_dir().join("config.toml"))
-}
-
-fn round_rgb(r: f32, g: f32, b: f32, brightness: f32) -> [u8; 3] {
-    [
-        (r * brightness).clamp(0.0, 255.0).round() as u8,
-        (g * brightness).clamp(0.0, 255.0).round() as u8,
-        (b * brightness).clamp(0.0, 255.0).round() as u8,
-    ]
+    Ok(monitors
+        .iter()
+        .map(|m| MonitorRes {
+            width: m.width_px,
+            height: m.height_px,
+        })
+        .collect())
 }
 
-fn average_rgb(rgb1: [u8; 3], rgb2: [u8; 3]) -> [u8; 3] {
-    [
-        ((rgb1[0] as u16 + rgb2[0] as u16) / 2) as u8,
-        ((rgb1[1] as u16 + rgb2[1] as u16) / 2) as u8,
-        ((rgb1[2] as u16 + rgb2[2] as u16) / 2) as u8,
-    ]
+fn round_rgb(rgb: [f32; 3], scale: [f32; 3]) -> [u8; 3] {
+    [0, 1, 2].map(|c| (rgb[c] * scale[c]).clamp(0.0, 255.0).round() as u8)
 }
 
 fn get_average_colors(
     regions: &[[i32; 4]],
-    cap: &mut VideoCapture,
-    previous_avg_colors: &[[u8; 3]],
-    brightness: f32,
-    smooth: bool,
+    samplers: &[Sampler],
+    frame: &Frame,
+    adjustment: &ColorAdjustment,
+    scale: [f32; 3],
 ) -> Result<Vec<[u8; 3]>, Box<dyn std::error::Error>> {
-    let mut img = Mat::default();
-    let ret = cap.read(&mut img)?;
-    if !ret {
-        return Ok(vec![]);
-    }
-
     let mut avg_colors = Vec::with_capacity(regions.len());
 
-    for (i, region) in regions.iter().enumerate() {
-        let x1 = region[0];
-        let y1 = region[1];
-        let x2 = region[2];
-        let y2 = region[3];
-
-        // Cut ROI from image
-        let roi = Mat::roi(&img, core::Rect::new(x1, y1, x2 - x1, y2 - y1))?;
-
-        // mean returns Scalar(B, G, R, A)
-        let mean = core::mean(&roi, &core::no_array())?;
-        let b = mean[0] as f32;
-        let g = mean[1] as f32;
-        let r = mean[2] as f32;
-
-        let rounded = round_rgb(r, g, b, brightness);
-        if smooth {
-            let avg = if previous_avg_colors.is_empty() {
-                average_rgb([0, 0, 0], rounded)
-            } else {
-                average_rgb(previous_avg_colors[i], rounded)
-            };
-            avg_colors.push(avg);
-        } else {
-            avg_colors.push(rounded);
-        }
+    for (region, sampler) in regions.iter().zip(samplers) {
+        let rgb = adjustment.apply(sampler.estimate(frame, region)?);
+        avg_colors.push(round_rgb(rgb, scale.map(|s| s * sampler.weight)));
     }
 
     Ok(avg_colors)
 }
 
-#[allow(clippy::too_many_arguments)]
 fn calculate_regions(
     monitors: &[MonitorRes],
-    left_led: &[i32],
-    up_led: &[i32],
-    right_led: &[i32],
-    down_led: &[i32],
-    left_up_indent: &[i32],
-    left_down_indent: &[i32],
-    up_left_indent: &[i32],
-    up_right_indent: &[i32],
-    right_up_indent: &[i32],
-    right_down_indent: &[i32],
-    down_left_indent: &[i32],
-    down_right_indent: &[i32],
+    monitor_configs: &[MonitorConfig],
     size: i32,
 ) -> Vec<Vec<[i32; 4]>> {
     let mut regions_list = Vec::with_capacity(monitors.len());
 
-    for (i, monitor) in monitors.iter().enumerate() {
+    for (monitor, cfg) in monitors.iter().zip(monitor_configs) {
+        let Some(led) = cfg.sides() else {
+            regions_list.push(cfg.layout_leds.iter().map(|l| l.region(*monitor)).collect());
+            continue;
+        };
+        let indent = &cfg.indent;
+
         // Main sizes
-        let inner_width_up = monitor.width - up_left_indent[i] - up_right_indent[i];
-        let inner_width_down = monitor.width - down_left_indent[i] - down_right_indent[i];
-        let inner_height_left = monitor.height - left_up_indent[i] - left_down_indent[i];
-        let inner_height_right = monitor.height - right_up_indent[i] - right_down_indent[i];
+        let inner_width_up = monitor.width - indent.up_left - indent.up_right;
+        let inner_width_down = monitor.width - indent.down_left - indent.down_right;
+        let inner_height_left = monitor.height - indent.left_up - indent.left_down;
+        let inner_height_right = monitor.height - indent.right_up - indent.right_down;
         let main_width = monitor.width;
         let main_height = monitor.height;
 
         // Steps between LEDs
-        let left_step = inner_height_left as f32 / left_led[i] as f32;
-        let up_step = inner_width_up as f32 / up_led[i] as f32;
-        let right_step = inner_height_right as f32 / right_led[i] as f32;
-        let down_step = inner_width_down as f32 / down_led[i] as f32;
+        let left_step = inner_height_left as f32 / led.left as f32;
+        let up_step = inner_width_up as f32 / led.up as f32;
+        let right_step = inner_height_right as f32 / led.right as f32;
+        let down_step = inner_width_down as f32 / led.down as f32;
 
         let mut monitor_regions: Vec<[i32; 4]> = Vec::new();
 
         // Left side (from bottom to top)
         {
-            let mut b = left_down_indent[i];
-            for a in 0..=left_led[i] {
-                let value = (left_step * a as f32).round() as i32 + left_down_indent[i];
+            let mut b = indent.left_down;
+            for a in 0..=led.left {
+                let value = (left_step * a as f32).round() as i32 + indent.left_down;
                 if a > 0 {
                     monitor_regions.push([
                         0,
-                        inner_height_left - value + left_up_indent[i],
+                        inner_height_left - value + indent.left_up,
                         size,
-                        inner_height_left - b + left_up_indent[i],
+                        inner_height_left - b + indent.left_up,
                     ]);
                 }
                 b = value;
             }
         }
 
+        // Top left corner
+        for _ in 0..led.corner.up_left {
+            monitor_regions.push([0, 0, size, size]);
+        }
+
         // Top side (from left to right)
         {
-            let mut b = up_left_indent[i];
-            for a in 0..=up_led[i] {
-                let value = (up_step * a as f32).round() as i32 + up_left_indent[i];
+            let mut b = indent.up_left;
+            for a in 0..=led.up {
+                let value = (up_step * a as f32).round() as i32 + indent.up_left;
                 if a > 0 {
                     monitor_regions.push([b, 0, value, size]);
                 }
@@ -330,11 +195,16 @@ fn calculate_regions(
             }
         }
 
+        // Top right corner
+        for _ in 0..led.corner.up_right {
+            monitor_regions.push([main_width - size, 0, main_width, size]);
+        }
+
         // Right side (from top to bottom)
         {
-            let mut b = right_up_indent[i];
-            for a in 0..=right_led[i] {
-                let value = (right_step * a as f32).round() as i32 + right_up_indent[i];
+            let mut b = indent.right_up;
+            for a in 0..=led.right {
+                let value = (right_step * a as f32).round() as i32 + indent.right_up;
                 if a > 0 {
                     monitor_regions.push([main_width - size, b, main_width, value]);
                 }
@@ -342,16 +212,26 @@ fn calculate_regions(
             }
         }
 
+        // Bottom right corner
+        for _ in 0..led.corner.down_right {
+            monitor_regions.push([
+                main_width - size,
+                main_height - size,
+                main_width,
+                main_height,
+            ]);
+        }
+
         // Bottom side (from right to left)
         {
-            let mut b = down_right_indent[i];
-            for a in 0..=down_led[i] {
-                let value = (down_step * a as f32).round() as i32 + down_right_indent[i];
+            let mut b = indent.down_right;
+            for a in 0..=led.down {
+                let value = (down_step * a as f32).round() as i32 + indent.down_right;
                 if a > 0 {
                     monitor_regions.push([
-                        inner_width_down - value + down_left_indent[i],
+                        inner_width_down - value + indent.down_left,
                         main_height - size,
-                        inner_width_down - b + down_left_indent[i],
+                        inner_width_down - b + indent.down_left,
                         main_height,
                     ]);
                 }
@@ -359,111 +239,100 @@ fn calculate_regions(
             }
         }
 
+        // Bottom left corner
+        for _ in 0..led.corner.down_left {
+            monitor_regions.push([0, main_height - size, size, main_height]);
+        }
+
+        arrange_strip(&mut monitor_regions, cfg);
         regions_list.push(monitor_regions);
     }
 
     regions_list
 }
 
-async fn send_data<'a>(
-    zone: &Zone<'a>,
-    data: &[[u8; 3]],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let colors: Vec<RGB8> = data
-        .iter()
-        .map(|rgb| RGB8::new(rgb[0], rgb[1], rgb[2]))
-        .collect();
-
-    // Send data
-    zone.set_leds(colors).await?;
-
-    Ok(())
-}
-
-fn prepare_serial_frame(colors: &[[u8; 3]], header: &[u8; 3]) -> Vec<u8> {
-    let num_leds = colors.len();
-    let count = num_leds.wrapping_sub(1);
-    let hi = (count >> 8) as u8;
-    let lo = count as u8;
-    let checksum = hi ^ lo ^ 0x55;
-
-    let mut buffer = Vec::with_capacity(6 + num_leds * 3 + 3);
-
-    buffer.extend_from_slice(header);
-    buffer.push(hi);
-    buffer.push(lo);
-    buffer.push(checksum);
-
-    for color in colors {
-        buffer.extend_from_slice(color);
+/// Reorders the LEDs of a monitor, listed clockwise from the lower end of the
+/// left side, to where its strip starts and the direction it runs in.
+/// LEDs of a layout file stay in the order of the file.
+///
+/// The LEDs of the start corner come last, so the strip leaves the corner
+/// along the side in `direction`.
+fn arrange_strip<T>(leds: &mut [T], cfg: &MonitorConfig) {
+    let len = leds.len() as i32;
+    if len == 0 {
+        return;
     }
 
-    let mut f1: u16 = 0;
-    let mut f2: u16 = 0;
-    let mut fext: u16 = 0;
-    for (pos, &byte) in buffer[6..].iter().enumerate() {
-        f1 = (f1 + byte as u16) % 255;
-        f2 = (f2 + f1) % 255;
-        fext = (fext + (byte as u16 ^ (pos & 0xff) as u16)) % 255;
-    }
-    if fext == 0x41 {
-        fext = 0xaa;
-    }
-    buffer.push(f1 as u8);
-    buffer.push(f2 as u8);
-    buffer.push(fext as u8);
-
-    buffer
-}
-
-async fn send_frame(
-    port: &mut SerialStream,
-    colors: &[[u8; 3]],
-    header: &[u8; 3],
-) -> Result<(), Box<dyn std::error::Error>> {
-    // 3-byte handshake prefix (triggers HyperHDR/Rp2040 handshake)
-    port.write_all(&[0x00, 0x00, 0x00]).await?;
-
-    let buffer = prepare_serial_frame(colors, header);
-    port.write_all(&buffer).await?;
-    port.flush().await?;
-
-    Ok(())
-}
-
-fn open_camera(cam: i32) -> VideoCapture {
-    let cap = VideoCapture::new(cam, videoio::CAP_V4L2).expect("Failed to open camera");
-    if !cap
-        .is_opened()
-        .expect("Failed to check if camera is opened")
-    {
-        eprintln!("Can't open camera {cam}");
+    let Some(led) = cfg.sides() else {
+        return;
+    };
+    let corner = &led.corner;
+    // Position of the first LED after each corner clockwise, and its LEDs
+    let (after, count) = match cfg.start_corner {
+        Corner::DownLeft => (0, corner.down_left),
+        Corner::UpLeft => (led.left + corner.up_left, corner.up_left),
+        Corner::UpRight => (
+            led.left + corner.up_left + led.up + corner.up_right,
+            corner.up_right,
+        ),
+        Corner::DownRight => (
+            led.left + corner.up_left + led.up + corner.up_right + led.right + corner.down_right,
+            corner.down_right,
+        ),
+    };
+    match cfg.direction {
+        Direction::Cw => leds.rotate_left(after.rem_euclid(len) as usize),
+        Direction::Ccw => {
+            leds.rotate_left((after - count).rem_euclid(len) as usize);
+            leds.reverse();
+        }
     }
-    cap
+    leds.rotate_left(cfg.start_offset.rem_euclid(len) as usize);
 }
 
 #[allow(clippy::too_many_arguments)]
 fn run_camera_task(
-    cam: i32,
-    region: Vec<[i32; 4]>,
-    brightness: f32,
-    smooth: bool,
-    delay_ms: u64,
+    mut source: impl FrameSource,
+    index: usize,
+    mut live: watch::Receiver<Arc<LiveSettings>>,
+    camera_fps: Arc<Vec<AtomicU32>>,
     manual_pause: Arc<AtomicBool>,
     screen_off: Arc<AtomicBool>,
     shutdown: Arc<AtomicBool>,
     mut on_frame: impl FnMut(&[[u8; 3]]),
 ) {
-    let mut cap = open_camera(cam);
-    let mut avg_colors = Vec::new();
+    let mut settings = live.borrow_and_update().clone();
+    let mut frames = 0u32;
+    let mut fps_since = time::Instant::now();
     let mut is_paused = manual_pause.load(Ordering::Relaxed) || screen_off.load(Ordering::Relaxed);
+    // Failed reads and repeated timestamps don't count as new frames
+    let mut last_fresh = time::Instant::now();
+    let mut last_timestamp = None;
+    let mut available = true;
+    let mut backoff = Backoff::new(time::Instant::now());
+    let mut tracker = BarTracker::new(time::Instant::now());
+    let mut bars = Bars::default();
+    let mut sampled = settings.regions[index].clone();
+    let mut scale = [settings.brightness; 3];
+    let mut scale_at: Option<time::Instant> = None;
 
     if is_paused {
-        let black = vec![[0u8; 3]; region.len()];
+        let black = vec![[0u8; 3]; settings.regions[index].len()];
         on_frame(&black);
     }
 
     while !shutdown.load(Ordering::Relaxed) {
+        if live.has_changed().unwrap_or(false) {
+            settings = live.borrow_and_update().clone();
+            sampled = settings.regions_within(index, bars);
+            scale_at = None;
+        }
+        if scale_at.is_none_or(|at| at.elapsed() >= SCHEDULE_INTERVAL) {
+            scale = settings.scale();
+            scale_at = Some(time::Instant::now());
+        }
+        let region = &settings.regions[index];
+
         let currently_paused =
             manual_pause.load(Ordering::Relaxed) || screen_off.load(Ordering::Relaxed);
 
@@ -473,6 +342,15 @@ fn run_camera_task(
             is_paused = true;
         } else if !currently_paused && is_paused {
             is_paused = false;
+            last_fresh = time::Instant::now();
+        }
+
+        let elapsed = fps_since.elapsed();
+        if elapsed >= time::Duration::from_secs(1) {
+            let fps = frames as f32 / elapsed.as_secs_f32();
+            camera_fps[index].store(fps.to_bits(), Ordering::Relaxed);
+            frames = 0;
+            fps_since = time::Instant::now();
         }
 
         if currently_paused {
@@ -480,34 +358,126 @@ fn run_camera_task(
             continue;
         }
 
-        let prev = &avg_colors;
-        let res =
-            get_average_colors(&region, &mut cap, prev, brightness, smooth).unwrap_or_default();
-        avg_colors = res.clone();
+        if last_fresh.elapsed() >= SOURCE_TIMEOUT {
+            if available {
+                eprintln!("Source of monitor {index} stopped delivering frames, reopening");
+                let black = vec![[0u8; 3]; region.len()];
+                on_frame(&black);
+                available = false;
+            }
+            if backoff.is_due(time::Instant::now()) {
+                match source.reopen() {
+                    Ok(()) => last_fresh = time::Instant::now(),
+                    Err(e) => eprintln!("Failed to reopen source of monitor {index}: {e}"),
+                }
+                // An open device may still deliver nothing, so the delay only
+                // resets once frames arrive
+                backoff.retry_later(time::Instant::now());
+            }
+            if last_fresh.elapsed() >= SOURCE_TIMEOUT {
+                std::thread::sleep(time::Duration::from_millis(100));
+                continue;
+            }
+        }
+
+        let frame = source
+            .next_frame()
+            .ok()
+            .flatten()
+            .filter(|f| f.timestamp_ms.is_none() || f.timestamp_ms != last_timestamp);
+        let res = match frame {
+            Some(frame) => {
+                frames += 1;
+                last_fresh = time::Instant::now();
+                last_timestamp = frame.timestamp_ms;
+                if !available {
+                    println!("Source of monitor {index} is back");
+                    available = true;
+                    backoff.reset();
+                }
+                let found = match settings.letterbox {
+                    Letterbox::Off => Bars::default(),
+                    Letterbox::Auto => tracker.update(detect_bars(&frame), time::Instant::now()),
+                    Letterbox::Fixed(fixed) => fixed,
+                };
+                if found != bars {
+                    bars = found;
+                    sampled = settings.regions_within(index, bars);
+                }
+                get_average_colors(
+                    &sampled,
+                    &settings.samplers[index],
+                    &frame,
+                    &settings.adjustment,
+                    scale,
+                )
+                .unwrap_or_default()
+            }
+            None => vec![],
+        };
         on_frame(&res);
-        std::thread::sleep(time::Duration::from_millis(delay_ms));
+        std::thread::sleep(time::Duration::from_millis(settings.delay_ms));
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let config = match args.config {
+    if let Some(Command::Ctl { request }) = &args.command {
+        let response = send_request(request)?;
+        print!("{response}");
+        if response.starts_with("error:") {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let config_path = match args.config {
         Some(path) => {
             println!("Using user config: {path:?}",);
-            load_config_from_file(&path)
+            path
         }
-        None => load_config(),
+        None => get_config_path().unwrap_or_else(|| {
+            eprintln!("{}", ConfigError::NoConfigDir);
+            std::process::exit(1);
+        }),
     };
+    let config = load_config_from_file(&config_path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    let available = get_monitors_info();
+    if let Err(ref e) = available {
+        eprintln!("Failed to query monitors, skipping resolution checks: {e}");
+    }
+    let issues = config.validate(available.as_deref().ok());
+    if !issues.is_empty() {
+        eprintln!("{}", ConfigError::Invalid(issues));
+        std::process::exit(1);
+    }
+    if args.check_config {
+        println!("Config is valid.");
+        return Ok(());
+    }
+    let available = available?;
+
+    if let Some(Command::Layout { monitor, file }) = &args.command {
+        let ids = config.monitor_ids();
+        let Some(&id) = ids.get(*monitor) else {
+            return Err(format!("monitor {monitor} not found ({} configured)", ids.len()).into());
+        };
+        let settings = LiveSettings::new(&config, &available);
+        Layout::from_regions(&settings.regions[*monitor], available[id]).save(file)?;
+        println!("Wrote the layout of monitor {monitor} to {file:?}");
+        return Ok(());
+    }
 
     let size = config.settings.size;
     let brightness = config.settings.brightness;
     let delay_ms = config.settings.delay_ms;
-    let smooth = config.settings.smooth;
-    let cams = config.settings.cams;
+    let cams: Vec<i32> = config.monitors.iter().map(|m| m.cam).collect();
     let device_id = config.settings.device_id;
-    let zone_id_list = config.settings.zone_id_list;
-    let monitor_id_list = config.settings.monitor_id_list;
 
     println!("Loaded config: size = {size}, brightness = {brightness}, delay = {delay_ms}ms");
     if args.paused {
@@ -516,37 +486,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let manual_pause = Arc::new(AtomicBool::new(args.paused));
     let screen_off = Arc::new(AtomicBool::new(false));
+    // Notified whenever `manual_pause` or `screen_off` change
+    let pause_changed = Arc::new(Notify::new());
     let shutdown = Arc::new(AtomicBool::new(false));
+    let camera_fps: Arc<Vec<AtomicU32>> =
+        Arc::new(cams.iter().map(|_| AtomicU32::new(0)).collect());
+    let reload_request = Arc::new(Notify::new());
 
-    let monitors = get_monitors_info(monitor_id_list)?;
-
-    let region_list = calculate_regions(
-        &monitors,
-        &config.led.left,
-        &config.led.up,
-        &config.led.right,
-        &config.led.down,
-        &config.indent.left_up,
-        &config.indent.left_down,
-        &config.indent.up_left,
-        &config.indent.up_right,
-        &config.indent.right_up,
-        &config.indent.right_down,
-        &config.indent.down_left,
-        &config.indent.down_right,
-        size,
-    );
+    let settings = LiveSettings::new(&config, &available);
+    let total_leds = settings.total_leds();
+    let led_counts = settings.led_counts();
+    let (live_tx, live_rx) = watch::channel(Arc::new(settings));
 
     let num_threads = cams.len().max(1);
     let rt = Builder::new_multi_thread()
         .worker_threads(num_threads)
-        .max_blocking_threads(num_threads)
+        // Cameras hold their blocking threads for good, keep one spare for
+        // short blocking calls made by libraries such as zbus
+        .max_blocking_threads(num_threads + 1)
         .enable_all()
         .build()?;
 
     rt.block_on(async move {
         // Spawn a task to listen for SIGUSR1 to toggle pause
         let paused_signal = manual_pause.clone();
+        let changed = pause_changed.clone();
         tokio::spawn(async move {
             let mut sigusr1 =
                 signal(SignalKind::user_defined1()).expect("Failed to listen for SIGUSR1");
@@ -554,6 +518,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 sigusr1.recv().await;
                 let current = paused_signal.load(Ordering::Relaxed);
                 paused_signal.store(!current, Ordering::Relaxed);
+                changed.notify_one();
                 println!("[Signal] Pause toggled. New state: {}", !current);
             }
         });
@@ -574,6 +539,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         // Spawn a task to poll DRM DPMS state automatically
         let paused_signal = screen_off.clone();
+        let changed = pause_changed.clone();
         tokio::spawn(async move {
             loop {
                 let mut is_dpms_off = false;
@@ -590,164 +556,393 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
 
-                paused_signal.store(is_dpms_off, Ordering::Relaxed);
+                if paused_signal.swap(is_dpms_off, Ordering::Relaxed) != is_dpms_off {
+                    changed.notify_one();
+                }
 
                 tokio::time::sleep(std::time::Duration::from_millis(500)).await;
             }
         });
 
-        let mut handles = Vec::with_capacity(cams.len());
-
-        if let Some(ref serial_cfg) = config.serial {
-            println!(
-                "Using serial port: {} at {} baud",
-                serial_cfg.port, serial_cfg.baud_rate
-            );
-
-            let port =
-                SerialStream::open(&tokio_serial::new(&serial_cfg.port, serial_cfg.baud_rate))
-                    .expect("Failed to open serial port");
-            let port = Arc::new(Mutex::new(port));
-
-            let total_leds: usize = region_list.iter().map(|r| r.len()).sum();
-            let led_counts: Vec<usize> = region_list.iter().map(|r| r.len()).collect();
-            let led_offsets: Vec<usize> = led_counts
-                .iter()
-                .scan(0, |acc, &x| {
-                    let start = *acc;
-                    *acc += x;
-                    Some(start)
-                })
-                .collect();
+        let mut handles = Vec::with_capacity(cams.len() + config.outputs.len());
+
+        let shared_colors: Arc<Mutex<Vec<[u8; 3]>>> =
+            Arc::new(Mutex::new(vec![[0u8; 3]; total_leds]));
+
+        let inputs = SinkInputs {
+            colors: shared_colors.clone(),
+            smoother: Arc::new(Mutex::new(Smoother::default())),
+            live: live_rx.clone(),
+            manual_pause: manual_pause.clone(),
+            screen_off: screen_off.clone(),
+            shutdown: shutdown.clone(),
+        };
+        let mut sinks = Vec::with_capacity(config.outputs.len());
+        for (index, output) in config.outputs.iter().enumerate() {
+            let monitors = output.monitor_indices(cams.len());
+            let total_leds = monitors.iter().map(|&m| led_counts[m]).sum();
+
+            let (health, handle) = match &output.kind {
+                OutputKind::Serial(serial_cfg) => {
+                    println!(
+                        "Using serial port: {} at {} baud",
+                        serial_cfg.port, serial_cfg.baud_rate
+                    );
+
+                    let header = serial_cfg.protocol.header();
+                    println!(
+                        "Using protocol: {} ({:02x} {:02x} {:02x})",
+                        serial_cfg.protocol, header[0], header[1], header[2]
+                    );
+
+                    let sink =
+                        SerialSink::new(&serial_cfg.port, serial_cfg.baud_rate, header, total_leds);
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+                OutputKind::Wled(wled_cfg) => {
+                    println!(
+                        "Using WLED at {}:{} ({}, timeout {}s)",
+                        wled_cfg.host, wled_cfg.port, wled_cfg.protocol, wled_cfg.timeout
+                    );
+
+                    let sink = WledSink::new(
+                        &wled_cfg.host,
+                        wled_cfg.port,
+                        wled_cfg.protocol,
+                        wled_cfg.timeout,
+                        total_leds,
+                    );
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+                OutputKind::Dmx(dmx_cfg) => {
+                    match &dmx_cfg.host {
+                        Some(host) => println!(
+                            "Using {} at {host}:{}, starting at universe {}",
+                            dmx_cfg.protocol,
+                            dmx_cfg.port(),
+                            dmx_cfg.universe
+                        ),
+                        None => println!(
+                            "Using {} {} on port {}, starting at universe {}",
+                            dmx_cfg.protocol,
+                            match dmx_cfg.protocol {
+                                DmxProtocol::E131 => "multicast",
+                                DmxProtocol::Artnet => "broadcast",
+                            },
+                            dmx_cfg.port(),
+                            dmx_cfg.universe
+                        ),
+                    }
 
-            let header = serial_cfg.protocol.header();
-            println!(
-                "Using protocol: {} ({:02x} {:02x} {:02x})",
-                serial_cfg.protocol, header[0], header[1], header[2]
-            );
+                    let sink = DmxSink::new(dmx_cfg, total_leds);
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+                OutputKind::Ddp(ddp_cfg) => {
+                    println!("Using DDP at {}:{}", ddp_cfg.host, ddp_cfg.port);
 
-            let shared_colors: Arc<Mutex<Vec<[u8; 3]>>> =
-                Arc::new(Mutex::new(vec![[0u8; 3]; total_leds]));
+                    let sink = DdpSink::new(&ddp_cfg.host, ddp_cfg.port, total_leds);
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+                OutputKind::Hyperion(hyperion_cfg) => {
+                    println!(
+                        "Using Hyperion at {}:{} as \"{}\" with priority {} ({})",
+                        hyperion_cfg.host,
+                        hyperion_cfg.port,
+                        hyperion_cfg.origin,
+                        hyperion_cfg.priority,
+                        hyperion_cfg.mode
+                    );
+
+                    let sink = HyperionSink::new(hyperion_cfg);
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+                OutputKind::Openrgb => {
+                    let mut offset = 0;
+                    let zones = monitors
+                        .iter()
+                        .map(|&m| {
+                            let monitor = &config.monitors[m];
+                            let slot = ZoneSlot {
+                                device_id: monitor.device_id.unwrap_or(device_id),
+                                zone_id: monitor.zone.expect("zone is checked by validate"),
+                                offset,
+                                len: led_counts[m],
+                            };
+                            offset += led_counts[m];
+                            slot
+                        })
+                        .collect();
+                    let sink = OpenRgbSink::new(zones);
+                    spawn_sink(sink, index, monitors, inputs.clone())
+                }
+            };
+            sinks.push(health);
+            handles.push(handle);
+        }
 
-            let port_clone = port.clone();
-            let colors_clone = shared_colors.clone();
-            let pause_clone = manual_pause.clone();
-            let off_clone = screen_off.clone();
+        for (i, &cam) in cams.iter().enumerate() {
+            let live = live_rx.clone();
+            let live_out = live_rx.clone();
+            let fps = camera_fps.clone();
+            let colors_out = shared_colors.clone();
+            let mp = manual_pause.clone();
+            let so = screen_off.clone();
             let sd = shutdown.clone();
-            let total = total_leds;
+
             handles.push(tokio::spawn(async move {
-                loop {
-                    if sd.load(Ordering::Relaxed) {
-                        let black = vec![[0u8; 3]; total];
-                        let mut port = port_clone.lock().await;
-                        for _ in 0..SHUTDOWN_BLACK_REPEATS {
-                            let _ = send_frame(&mut port, &black, header).await;
+                tokio::task::spawn_blocking(move || {
+                    let source = V4l2Source::open(cam);
+                    run_camera_task(source, i, live, fps, mp, so, sd, move |frame| {
+                        let mut colors = colors_out.blocking_lock();
+                        let live = live_out.borrow();
+                        // Frames sampled with the settings before a reload are dropped
+                        if frame.len() == live.regions[i].len() && colors.len() == live.total_leds()
+                        {
+                            let offset = live.offset(i);
+                            colors[offset..offset + frame.len()].copy_from_slice(frame);
                         }
-                        break;
-                    }
-
-                    let is_paused =
-                        pause_clone.load(Ordering::Relaxed) || off_clone.load(Ordering::Relaxed);
-
-                    let colors = if is_paused {
-                        vec![[0u8; 3]; total]
-                    } else {
-                        colors_clone.lock().await.clone()
-                    };
-
-                    let mut port = port_clone.lock().await;
-                    if let Err(e) = send_frame(&mut port, &colors, header).await {
-                        eprintln!("Serial send error: {e}");
-                    }
-                    drop(port);
-
-                    tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
-                }
+                    });
+                })
+                .await
+                .unwrap();
             }));
+        }
 
-            for (i, &cam) in cams.iter().enumerate() {
-                let region = region_list[i].clone();
-                let offset = led_offsets[i];
-                let colors_out = shared_colors.clone();
-                let mp = manual_pause.clone();
-                let so = screen_off.clone();
-                let sd = shutdown.clone();
-
-                handles.push(tokio::spawn(async move {
-                    tokio::task::spawn_blocking(move || {
-                        run_camera_task(cam, region, brightness, smooth, delay_ms, mp, so, sd, {
-                            let colors_out = colors_out.clone();
-                            move |frame| {
-                                let mut colors = colors_out.blocking_lock();
-                                if !frame.is_empty() {
-                                    colors[offset..offset + frame.len()].copy_from_slice(frame);
-                                }
-                            }
-                        });
-                    })
-                    .await
-                    .unwrap();
-                }));
-            }
-        } else {
-            let client = OpenRgbClient::connect().await.unwrap();
-            for (i, &cam) in cams.iter().enumerate() {
-                let region = region_list[i].clone();
-                let brightness = brightness;
-                let controller = client.get_controller(device_id).await.unwrap();
-                let zone_id = zone_id_list[i];
-
-                let manual_pause_clone = manual_pause.clone();
-                let screen_off_clone = screen_off.clone();
-                let sd = shutdown.clone();
-
-                handles.push(tokio::spawn(async move {
-                    tokio::task::spawn_blocking(move || {
-                        let zone = controller.get_zone(zone_id).unwrap();
-                        run_camera_task(
-                            cam,
-                            region,
-                            brightness,
-                            smooth,
-                            delay_ms,
-                            manual_pause_clone,
-                            screen_off_clone,
-                            sd,
-                            |frame| {
-                                tokio::runtime::Handle::current()
-                                    .block_on(send_data(&zone, frame))
-                                    .expect("Failed to send data");
-                            },
-                        );
-                    })
-                    .await
-                    .unwrap();
-                }));
-            }
+        // Reload settings on SIGHUP, control requests and config file changes
+        tokio::spawn(run_reload_task(
+            config_path,
+            config,
+            live_tx.clone(),
+            shared_colors.clone(),
+            reload_request.clone(),
+        ));
+
+        // Expose pause state, brightness and profiles on the session bus
+        #[cfg(feature = "dbus")]
+        {
+            let mp = manual_pause.clone();
+            let so = screen_off.clone();
+            let changed = pause_changed.clone();
+            let live = live_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = dbus::run_dbus_service(mp, so, changed, live).await {
+                    eprintln!("[D-Bus] Service stopped: {e}");
+                }
+            });
         }
 
-        if shutdown.load(Ordering::Relaxed) {
-            if config.serial.is_none()
-                && let Ok(client) = OpenRgbClient::connect().await
-                && let Ok(controller) = client.get_controller(device_id).await
-            {
-                for _ in 0..SHUTDOWN_BLACK_REPEATS {
-                    for &zone_id in &zone_id_list {
-                        if let Ok(zone) = controller.get_zone(zone_id) {
-                            let _ = zone.set_all_leds(RGB8::new(0, 0, 0)).await;
-                        }
-                    }
+        // Serve the control socket for `ambiway ctl`
+        let socket = match socket_path() {
+            Ok(socket) => match control::bind(&socket) {
+                Ok(listener) => {
+                    println!("Control socket: {socket:?}");
+                    tokio::spawn(run_control_task(
+                        listener,
+                        Control {
+                            manual_pause: manual_pause.clone(),
+                            screen_off: screen_off.clone(),
+                            pause_changed,
+                            live: live_tx,
+                            camera_fps: camera_fps.clone(),
+                            sinks,
+                            reload: reload_request,
+                        },
+                    ));
+                    Some(socket)
+                }
+                Err(e) => {
+                    eprintln!("Failed to bind control socket {socket:?}: {e}");
+                    None
                 }
+            },
+            Err(e) => {
+                eprintln!("No control socket: {e}");
+                None
             }
-            println!("Shutdown complete.");
-        }
+        };
 
         for h in handles {
             if let Err(e) = h.await {
                 eprintln!("Task failed: {e}");
             }
         }
+        if let Some(socket) = socket {
+            let _ = std::fs::remove_file(socket);
+        }
+        println!("Shutdown complete.");
     });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::tests::parse, estimate::Side};
+
+    const RES: MonitorRes = MonitorRes {
+        width: 100,
+        height: 60,
+    };
+
+    /// Monitor with the `led` table and extra `[[monitor]]` keys
+    fn monitor(led: &str, keys: &str) -> MonitorConfig {
+        parse(&format!(
+            "[settings]\nsize = 10\n[[monitor]]\ncam = 0\nled = {led}\n{keys}"
+        ))
+        .monitors
+        .remove(0)
+    }
+
+    #[test]
+    fn corners_sit_between_their_sides() {
+        let cfg = monitor(
+            "{ left = 2, up = 3, right = 2, down = 3, \
+             corner = { up_left = 1, up_right = 2, down_left = 1 } }",
+            "",
+        );
+        let regions = calculate_regions(&[RES], &[cfg], 10).remove(0);
+        assert_eq!(
+            regions,
+            [
+                // Left, from the bottom up
+                [0, 30, 10, 60],
+                [0, 0, 10, 30],
+                // Top left corner
+                [0, 0, 10, 10],
+                // Top, from left to right
+                [0, 0, 33, 10],
+                [33, 0, 67, 10],
+                [67, 0, 100, 10],
+                // Both LEDs of the top right corner
+                [90, 0, 100, 10],
+                [90, 0, 100, 10],
+                // Right, from the top down
+                [90, 0, 100, 30],
+                [90, 30, 100, 60],
+                // No bottom right corner, then the bottom from right to left
+                [67, 50, 100, 60],
+                [33, 50, 67, 60],
+                [0, 50, 33, 60],
+                // Bottom left corner, the start corner comes last
+                [0, 50, 10, 60],
+            ]
+        );
+    }
+
+    /// One LED per side and corner, except two on the left and right
+    const SMALL: &str = "{ left = 2, up = 1, right = 2, down = 1, \
+                         corner = { up_left = 1, up_right = 1, down_right = 1, down_left = 1 } }";
+
+    /// LEDs of `SMALL` clockwise from the lower end of the left side, with the
+    /// region and side each one gets
+    const CLOCKWISE: [(&str, [i32; 4], Side); 10] = [
+        ("L0", [0, 30, 10, 60], Side::Left),
+        ("L1", [0, 0, 10, 30], Side::Left),
+        ("UL", [0, 0, 10, 10], Side::Left),
+        ("U0", [0, 0, 100, 10], Side::Up),
+        ("UR", [90, 0, 100, 10], Side::Up),
+        ("R0", [90, 0, 100, 30], Side::Right),
+        ("R1", [90, 30, 100, 60], Side::Right),
+        ("DR", [90, 50, 100, 60], Side::Right),
+        ("D0", [0, 50, 100, 60], Side::Down),
+        ("DL", [0, 50, 10, 60], Side::Down),
+    ];
+
+    /// Strip order without an offset for every start corner and direction
+    const ORDERS: [(&str, &str, [&str; 10]); 8] = [
+        (
+            "down_left",
+            "cw",
+            ["L0", "L1", "UL", "U0", "UR", "R0", "R1", "DR", "D0", "DL"],
+        ),
+        (
+            "down_left",
+            "ccw",
+            ["D0", "DR", "R1", "R0", "UR", "U0", "UL", "L1", "L0", "DL"],
+        ),
+        (
+            "up_left",
+            "cw",
+            ["U0", "UR", "R0", "R1", "DR", "D0", "DL", "L0", "L1", "UL"],
+        ),
+        (
+            "up_left",
+            "ccw",
+            ["L1", "L0", "DL", "D0", "DR", "R1", "R0", "UR", "U0", "UL"],
+        ),
+        (
+            "up_right",
+            "cw",
+            ["R0", "R1", "DR", "D0", "DL", "L0", "L1", "UL", "U0", "UR"],
+        ),
+        (
+            "up_right",
+            "ccw",
+            ["U0", "UL", "L1", "L0", "DL", "D0", "DR", "R1", "R0", "UR"],
+        ),
+        (
+            "down_right",
+            "cw",
+            ["D0", "DL", "L0", "L1", "UL", "U0", "UR", "R0", "R1", "DR"],
+        ),
+        (
+            "down_right",
+            "ccw",
+            ["R1", "R0", "UR", "U0", "UL", "L1", "L0", "DL", "D0", "DR"],
+        ),
+    ];
+
+    #[test]
+    fn strip_starts_at_its_corner_and_offset() {
+        for (corner, direction, order) in ORDERS {
+            for offset in [0i32, 1, 3, 9, 10, 13, -1] {
+                let cfg = monitor(
+                    SMALL,
+                    &format!(
+                        "start_corner = \"{corner}\"\n\
+                         direction = \"{direction}\"\n\
+                         start_offset = {offset}\n"
+                    ),
+                );
+                let mut leds = CLOCKWISE.map(|(name, _, _)| name);
+                arrange_strip(&mut leds, &cfg);
+
+                // The first LED is `offset` LEDs from the corner along the strip
+                let mut expected = order;
+                expected.rotate_left(offset.rem_euclid(10) as usize);
+                assert_eq!(leds, expected, "{corner} {direction} {offset}");
+            }
+        }
+    }
+
+    #[test]
+    fn samplers_follow_the_rotated_regions() {
+        for (corner, direction, _) in ORDERS {
+            for offset in [0, 4, -3] {
+                let cfg = monitor(
+                    SMALL,
+                    &format!(
+                        "start_corner = \"{corner}\"\n\
+                         direction = \"{direction}\"\n\
+                         start_offset = {offset}\n"
+                    ),
+                );
+                let regions = calculate_regions(&[RES], std::slice::from_ref(&cfg), 10).remove(0);
+                let samplers = Sampler::for_monitor(&cfg, Default::default());
+                assert_eq!(regions.len(), CLOCKWISE.len());
+                assert_eq!(samplers.len(), regions.len());
+                for (region, sampler) in regions.iter().zip(&samplers) {
+                    let (name, _, side) = CLOCKWISE
+                        .iter()
+                        .find(|(_, r, _)| r == region)
+                        .expect("every region is listed once");
+                    assert_eq!(
+                        sampler.side, *side,
+                        "{name} with {corner} {direction} {offset}"
+                    );
+                }
+            }
+        }
+    }
+}
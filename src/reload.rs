@@ -0,0 +1,361 @@
+use chrono::Local;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    select,
+    signal::unix::{SignalKind, signal},
+    sync::{Mutex, Notify, mpsc, watch},
+};
+
+use crate::{
+    MonitorRes, calculate_regions,
+    color::{ColorAdjustment, ColorCorrection, Smoothing},
+    config::{Config, ConfigError, CurrentLimit, MonitorConfig, Profile, load_config_from_file},
+    estimate::Sampler,
+    get_monitors_info,
+    letterbox::{Bars, Letterbox},
+    schedule::Schedule,
+};
+
+/// Settings that are swapped into running camera and sink tasks on reload
+#[derive(Clone, Debug)]
+pub struct LiveSettings {
+    pub brightness: f32,
+    pub smooth: bool,
+    pub smoothing: Smoothing,
+    pub delay_ms: u64,
+    pub adjustment: ColorAdjustment,
+    /// Sampling regions of each monitor
+    pub regions: Vec<Vec<[i32; 4]>>,
+    /// Estimator and side of each region, same layout as `regions`
+    pub samplers: Vec<Vec<Sampler>>,
+    pub letterbox: Letterbox,
+    /// Resolution and config of each monitor, to fit the regions to bars
+    monitors: Vec<(MonitorRes, MonitorConfig)>,
+    size: i32,
+    /// Gamma and white balance of each output
+    pub corrections: Vec<ColorCorrection>,
+    /// Current budget of each output
+    pub current_limits: Vec<Option<CurrentLimit>>,
+    /// Brightness and color temperature over the day
+    pub schedule: Option<Schedule>,
+    /// Active profile, `None` means plain `[settings]`
+    pub profile: Option<String>,
+    profiles: HashMap<String, Profile>,
+    /// Values from `[settings]`, restored when switching profiles
+    base: Profile,
+}
+
+impl LiveSettings {
+    /// `available` are all monitors reported by xrandr
+    pub fn new(config: &Config, available: &[MonitorRes]) -> Self {
+        let monitors: Vec<MonitorRes> = config
+            .monitor_ids()
+            .into_iter()
+            .map(|id| available[id])
+            .collect();
+        let smooth_ms = config.settings.smooth_ms;
+
+        Self {
+            brightness: config.settings.brightness,
+            smooth: config.settings.smooth,
+            smoothing: Smoothing {
+                attack: Duration::from_millis(config.settings.attack_ms.unwrap_or(smooth_ms)),
+                decay: Duration::from_millis(config.settings.decay_ms.unwrap_or(smooth_ms)),
+                scene_cut: config.settings.scene_cut,
+            },
+            delay_ms: config.settings.delay_ms,
+            adjustment: ColorAdjustment {
+                saturation: config.settings.saturation,
+                vibrance: config.settings.vibrance,
+                hue_shift: config.settings.hue_shift,
+                white_threshold: config.settings.white_threshold,
+            },
+            regions: calculate_regions(&monitors, &config.monitors, config.settings.size),
+            samplers: config
+                .monitors
+                .iter()
+                .map(|m| Sampler::for_monitor(m, config.settings.estimator))
+                .collect(),
+            letterbox: config.settings.letterbox,
+            monitors: monitors
+                .iter()
+                .copied()
+                .zip(config.monitors.iter().cloned())
+                .collect(),
+            size: config.settings.size,
+            corrections: config
+                .outputs
+                .iter()
+                .map(|o| {
+                    ColorCorrection::new(
+                        o.gamma.unwrap_or(config.settings.gamma),
+                        o.gain.unwrap_or(config.settings.gain),
+                    )
+                })
+                .collect(),
+            current_limits: config
+                .outputs
+                .iter()
+                .map(|o| o.current_limit.or(config.settings.current_limit))
+                .collect(),
+            schedule: config.schedule.clone(),
+            profile: None,
+            profiles: config.profiles.clone(),
+            base: Profile {
+                brightness: Some(config.settings.brightness),
+                smooth: Some(config.settings.smooth),
+                delay_ms: Some(config.settings.delay_ms),
+            },
+        }
+    }
+
+    /// Switches to the named profile, or back to `[settings]` with `None`
+    pub fn set_profile(&mut self, name: Option<&str>) -> Result<(), String> {
+        let profile = match name {
+            Some(name) => Some(
+                self.profiles
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("unknown profile {name}"))?,
+            ),
+            None => None,
+        };
+
+        let base = self.base.clone();
+        self.apply(&base);
+        if let Some(profile) = profile {
+            self.apply(&profile);
+        }
+        self.profile = name.map(str::to_string);
+        Ok(())
+    }
+
+    fn apply(&mut self, profile: &Profile) {
+        if let Some(brightness) = profile.brightness {
+            self.brightness = brightness;
+        }
+        if let Some(smooth) = profile.smooth {
+            self.smooth = smooth;
+        }
+        if let Some(delay_ms) = profile.delay_ms {
+            self.delay_ms = delay_ms;
+        }
+    }
+
+    /// Names of all `[profile.<name>]` tables, sorted
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn led_counts(&self) -> Vec<usize> {
+        self.regions.iter().map(|r| r.len()).collect()
+    }
+
+    pub fn total_leds(&self) -> usize {
+        self.regions.iter().map(|r| r.len()).sum()
+    }
+
+    /// Sampling regions of `monitor` fitted to the picture inside `bars`.
+    ///
+    /// Falls back to the full monitor if the picture is too small for the
+    /// indents and region size.
+    pub fn regions_within(&self, monitor: usize, bars: Bars) -> Vec<[i32; 4]> {
+        let full = &self.regions[monitor];
+        if bars == Bars::default() {
+            return full.clone();
+        }
+
+        let (res, config) = &self.monitors[monitor];
+        let picture = MonitorRes {
+            width: res.width - bars.left - bars.right,
+            height: res.height - bars.top - bars.bottom,
+        };
+        if self.size > picture.width.min(picture.height) {
+            return full.clone();
+        }
+        let regions: Vec<[i32; 4]> =
+            calculate_regions(&[picture], std::slice::from_ref(config), self.size)
+                .remove(0)
+                .into_iter()
+                .map(|[x1, y1, x2, y2]| {
+                    [x1 + bars.left, y1 + bars.top, x2 + bars.left, y2 + bars.top]
+                })
+                .collect();
+
+        let inside = |&[x1, y1, x2, y2]: &[i32; 4]| {
+            x1 >= bars.left
+                && y1 >= bars.top
+                && x2 <= res.width - bars.right
+                && y2 <= res.height - bars.bottom
+                && x1 < x2
+                && y1 < y2
+        };
+        if regions.iter().all(inside) {
+            regions
+        } else {
+            full.clone()
+        }
+    }
+
+    /// Per-channel multipliers of the captured colors, `brightness` with the
+    /// schedule applied at the current time
+    pub fn scale(&self) -> [f32; 3] {
+        match &self.schedule {
+            Some(schedule) => schedule.at(&Local::now()).scale(self.brightness),
+            None => [self.brightness; 3],
+        }
+    }
+
+    /// Position of the first LED of `monitor` in the combined buffer
+    pub fn offset(&self, monitor: usize) -> usize {
+        self.regions[..monitor].iter().map(|r| r.len()).sum()
+    }
+}
+
+/// Reloads the config on SIGHUP, on `request` or when it or one of its layout
+/// files changes on disk.
+///
+/// `running` is the config the daemon was started with. Cameras and outputs
+/// can't be swapped at runtime, so adding or removing them is rejected and
+/// other changes to them are reported and ignored, only the gamma, gain and
+/// current limit of outputs are applied.
+pub async fn run_reload_task(
+    path: PathBuf,
+    running: Config,
+    live: watch::Sender<Arc<LiveSettings>>,
+    colors: Arc<Mutex<Vec<[u8; 3]>>>,
+    request: Arc<Notify>,
+) {
+    let mut sighup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+
+    let (tx, mut changes) = mpsc::unbounded_channel();
+    let watch = |layouts: &[PathBuf]| {
+        let files: Vec<PathBuf> = std::iter::once(path.clone())
+            .chain(layouts.iter().cloned())
+            .collect();
+        watch_files(&files, tx.clone())
+            .inspect_err(|e| eprintln!("[Reload] Failed to watch {files:?}: {e}"))
+            .ok()
+    };
+    let mut layouts = running.layout_paths(&path);
+    let mut _watcher = watch(&layouts);
+
+    loop {
+        let trigger = select! {
+            _ = sighup.recv() => "SIGHUP",
+            Some(()) = changes.recv() => "file change",
+            _ = request.notified() => "control request",
+        };
+
+        // Editors often save in several steps, let them finish
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while changes.try_recv().is_ok() {}
+
+        println!("[Reload] {trigger}, reloading {path:?}");
+        match reload(&path, &running).map_err(|e| e.to_string()) {
+            Ok((config, mut settings)) => {
+                if config.layout_paths(&path) != layouts {
+                    layouts = config.layout_paths(&path);
+                    _watcher = watch(&layouts);
+                }
+                let profile = live.borrow().profile.clone();
+                if let Err(e) = settings.set_profile(profile.as_deref()) {
+                    eprintln!("[Reload] {e}, falling back to [settings]");
+                }
+                let total_leds = settings.total_leds();
+                println!(
+                    "[Reload] Applied: brightness = {}, delay = {}ms, smooth = {}, {} LEDs",
+                    settings.brightness, settings.delay_ms, settings.smooth, total_leds
+                );
+                // Readers lock the buffer before reading the settings, so they
+                // see the new size only together with the new settings
+                let mut colors = colors.lock().await;
+                colors.resize(total_leds, [0u8; 3]);
+                live.send_replace(Arc::new(settings));
+                drop(colors);
+            }
+            Err(e) => eprintln!("[Reload] {e}\nKeeping current settings."),
+        }
+    }
+}
+
+fn reload(
+    path: &Path,
+    running: &Config,
+) -> Result<(Config, LiveSettings), Box<dyn std::error::Error>> {
+    let config = load_config_from_file(path)?;
+    let available = get_monitors_info()?;
+    let issues = config.validate(Some(&available));
+    if !issues.is_empty() {
+        return Err(ConfigError::Invalid(issues).into());
+    }
+
+    if config.monitors.len() != running.monitors.len() {
+        return Err(format!(
+            "Number of monitors changed from {} to {}, restart ambiway to apply",
+            running.monitors.len(),
+            config.monitors.len()
+        )
+        .into());
+    }
+    // Sinks look up their settings by the position of their output
+    if config.outputs.len() != running.outputs.len() {
+        return Err(format!(
+            "Number of outputs changed from {} to {}, restart ambiway to apply",
+            running.outputs.len(),
+            config.outputs.len()
+        )
+        .into());
+    }
+    if running.needs_restart(&config) {
+        eprintln!("[Reload] Camera or output changes need a restart and were ignored");
+    }
+
+    let settings = LiveSettings::new(&config, &available);
+    Ok((config, settings))
+}
+
+/// Watches the directories of `files`, since editors usually replace a file
+fn watch_files(
+    files: &[PathBuf],
+    tx: mpsc::UnboundedSender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    // Events name the file inside the directory as it was passed to `watch`
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut watched = Vec::with_capacity(files.len());
+    for file in files {
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = dir.canonicalize()?;
+        if let Some(name) = file.file_name() {
+            watched.push(dir.join(name));
+        }
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.iter().any(|p| watched.contains(p))
+        {
+            let _ = tx.send(());
+        }
+    })?;
+    for dir in &dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
@@ -0,0 +1,358 @@
+use chrono::{DateTime, Datelike, Offset, TimeZone, Timelike};
+use serde::Deserialize;
+use std::{f64::consts::PI, str::FromStr};
+
+const MINUTES_PER_DAY: f64 = 1440.0;
+/// Temperature that leaves colors as they are
+pub const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+/// Zenith of the sun at sunrise and sunset, including refraction, in degrees
+const SUN_ZENITH: f64 = 90.833;
+
+/// `[schedule]` table, brightness and color temperature over the day
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Schedule {
+    /// Needed by points at sunrise or sunset
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Points in any order, values in between are interpolated
+    #[serde(rename = "point", default)]
+    pub points: Vec<SchedulePoint>,
+}
+
+/// One `[[schedule.point]]` entry
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct SchedulePoint {
+    pub at: TimeOfDay,
+    /// Color temperature in Kelvin
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+    /// Multiplier of `settings.brightness`
+    #[serde(default = "default_brightness")]
+    pub brightness: f32,
+}
+
+fn default_temperature() -> f32 {
+    NEUTRAL_TEMPERATURE
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+/// Time of a schedule point: `hh:mm`, or `sunrise` / `sunset` with an
+/// optional `+hh:mm` or `-hh:mm` offset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TimeOfDay {
+    /// Minutes after midnight
+    Clock(i32),
+    /// Offset from sunrise in minutes
+    Sunrise(i32),
+    /// Offset from sunset in minutes
+    Sunset(i32),
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time {s}, expected hh:mm, sunrise or sunset[+-hh:mm]");
+        let minutes = |hm: &str| -> Option<i32> {
+            let (h, m) = hm.split_once(':')?;
+            let (h, m) = (h.parse::<i32>().ok()?, m.parse::<i32>().ok()?);
+            ((0..24).contains(&h) && (0..60).contains(&m)).then_some(h * 60 + m)
+        };
+        let offset = |rest: &str| -> Option<i32> {
+            match rest.chars().next() {
+                None => Some(0),
+                Some('+') => minutes(&rest[1..]),
+                Some('-') => minutes(&rest[1..]).map(|m| -m),
+                Some(_) => None,
+            }
+        };
+
+        let time = if let Some(rest) = s.strip_prefix("sunrise") {
+            offset(rest).map(TimeOfDay::Sunrise)
+        } else if let Some(rest) = s.strip_prefix("sunset") {
+            offset(rest).map(TimeOfDay::Sunset)
+        } else {
+            minutes(s).map(TimeOfDay::Clock)
+        };
+        time.ok_or_else(invalid)
+    }
+}
+
+impl TimeOfDay {
+    fn uses_sun(&self) -> bool {
+        !matches!(self, TimeOfDay::Clock(_))
+    }
+}
+
+/// Brightness and color temperature the schedule gives at some time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScheduleState {
+    pub brightness: f32,
+    pub temperature: f32,
+}
+
+impl Default for ScheduleState {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            temperature: NEUTRAL_TEMPERATURE,
+        }
+    }
+}
+
+impl ScheduleState {
+    /// Per-channel multipliers for `brightness`, with the color temperature
+    pub fn scale(&self, brightness: f32) -> [f32; 3] {
+        let white = temperature_rgb(NEUTRAL_TEMPERATURE);
+        let rgb = temperature_rgb(self.temperature);
+        [0, 1, 2].map(|c| brightness * self.brightness * (rgb[c] / white[c]).min(1.0))
+    }
+}
+
+impl Schedule {
+    /// Whether some point depends on the position of the sun
+    pub fn uses_sun(&self) -> bool {
+        self.points.iter().any(|p| p.at.uses_sun())
+    }
+
+    /// State at `now`, interpolated between the points before and after it.
+    ///
+    /// The clock is passed in so any time and time zone can be checked. Points
+    /// at sunrise or sunset are skipped on days the sun doesn't rise or set.
+    pub fn at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> ScheduleState {
+        let sun = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => sun_times(now, latitude, longitude),
+            _ => None,
+        };
+
+        let mut points: Vec<(f64, &SchedulePoint)> = self
+            .points
+            .iter()
+            .filter_map(|p| {
+                let minute = match (p.at, sun) {
+                    (TimeOfDay::Clock(m), _) => m as f64,
+                    (TimeOfDay::Sunrise(m), Some((sunrise, _))) => sunrise + m as f64,
+                    (TimeOfDay::Sunset(m), Some((_, sunset))) => sunset + m as f64,
+                    _ => return None,
+                };
+                Some((minute.rem_euclid(MINUTES_PER_DAY), p))
+            })
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+            return ScheduleState::default();
+        };
+        let minute = now.hour() as f64 * 60.0 + now.minute() as f64 + now.second() as f64 / 60.0;
+        // The day wraps, so before the first point it goes from the last one
+        let before = points
+            .iter()
+            .rev()
+            .find(|(m, _)| *m <= minute)
+            .copied()
+            .unwrap_or((last.0 - MINUTES_PER_DAY, last.1));
+        let after = points
+            .iter()
+            .find(|(m, _)| *m > minute)
+            .copied()
+            .unwrap_or((first.0 + MINUTES_PER_DAY, first.1));
+
+        let span = after.0 - before.0;
+        let t = if span > 0.0 {
+            ((minute - before.0) / span) as f32
+        } else {
+            0.0
+        };
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        ScheduleState {
+            brightness: lerp(before.1.brightness, after.1.brightness),
+            temperature: lerp(before.1.temperature, after.1.temperature),
+        }
+    }
+}
+
+/// Local sunrise and sunset of the day of `now`, in minutes after midnight,
+/// `None` during polar day or night. Uses the NOAA approximation.
+fn sun_times<Tz: TimeZone>(
+    now: &DateTime<Tz>,
+    latitude: f64,
+    longitude: f64,
+) -> Option<(f64, f64)> {
+    let day = now.ordinal0() as f64;
+    let year = 2.0 * PI / 365.0 * day;
+    let equation_of_time = 229.18
+        * (0.000075 + 0.001868 * year.cos()
+            - 0.032077 * year.sin()
+            - 0.014615 * (2.0 * year).cos()
+            - 0.040849 * (2.0 * year).sin());
+    let declination = 0.006918 - 0.399912 * year.cos() + 0.070257 * year.sin()
+        - 0.006758 * (2.0 * year).cos()
+        + 0.000907 * (2.0 * year).sin()
+        - 0.002697 * (3.0 * year).cos()
+        + 0.00148 * (3.0 * year).sin();
+
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = SUN_ZENITH.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+
+    let utc_offset = now.offset().fix().local_minus_utc() as f64 / 60.0;
+    let noon = 720.0 - 4.0 * longitude - equation_of_time + utc_offset;
+    Some((noon - 4.0 * hour_angle, noon + 4.0 * hour_angle))
+}
+
+/// Color of a black body at `kelvin`, after Tanner Helland's approximation
+fn temperature_rgb(kelvin: f32) -> [f32; 3] {
+    let t = kelvin / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let g = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.075514846)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    [r, g, b].map(|c| c.clamp(0.0, 255.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn time(
+        utc_hours: i32,
+        date: (i32, u32, u32),
+        hour: u32,
+        minute: u32,
+    ) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(utc_hours * 3600)
+            .unwrap()
+            .with_ymd_and_hms(date.0, date.1, date.2, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn point(at: &str, temperature: f32, brightness: f32) -> SchedulePoint {
+        SchedulePoint {
+            at: at.parse().unwrap(),
+            temperature,
+            brightness,
+        }
+    }
+
+    /// Minutes after midnight as `hh:mm`
+    fn clock(minutes: f64) -> String {
+        let minutes = minutes.round() as i32;
+        format!("{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+
+    #[test]
+    fn interpolates_across_midnight() {
+        let schedule = Schedule {
+            latitude: None,
+            longitude: None,
+            points: vec![point("06:00", 6500.0, 1.0), point("22:00", 3000.0, 0.5)],
+        };
+        let at = |hour, minute| schedule.at(&time(0, (2026, 1, 10), hour, minute));
+
+        assert_eq!(at(22, 0).temperature, 3000.0);
+        // 22:00 to 06:00 takes 8 hours, 02:00 is halfway
+        assert_eq!(
+            at(2, 0),
+            ScheduleState {
+                brightness: 0.75,
+                temperature: 4750.0
+            }
+        );
+        assert_eq!(at(23, 0).temperature, 3000.0 + 3500.0 / 8.0);
+        assert_eq!(at(12, 0).brightness, 0.8125);
+    }
+
+    #[test]
+    fn sun_times_match_noaa() {
+        // NOAA solar calculator: Berlin 04:43 to 21:33 CEST, New York 07:16 to 16:32 EST
+        let cases = [
+            (
+                52.52,
+                13.405,
+                time(2, (2026, 6, 21), 12, 0),
+                4 * 60 + 43,
+                21 * 60 + 33,
+            ),
+            (
+                40.7128,
+                -74.006,
+                time(-5, (2026, 12, 21), 12, 0),
+                7 * 60 + 16,
+                16 * 60 + 32,
+            ),
+        ];
+        for (latitude, longitude, now, sunrise, sunset) in cases {
+            let (rise, set) = sun_times(&now, latitude, longitude).unwrap();
+            assert!(
+                (rise - sunrise as f64).abs() <= 2.0,
+                "sunrise {}",
+                clock(rise)
+            );
+            assert!((set - sunset as f64).abs() <= 2.0, "sunset {}", clock(set));
+        }
+    }
+
+    #[test]
+    fn skips_sun_points_during_polar_day_and_night() {
+        // Tromsø
+        let schedule = Schedule {
+            latitude: Some(69.65),
+            longitude: Some(18.96),
+            points: vec![point("12:00", 6500.0, 1.0), point("sunset", 2700.0, 0.3)],
+        };
+        for date in [(2026, 6, 21), (2026, 12, 21)] {
+            let now = time(1, date, 20, 0);
+            assert_eq!(sun_times(&now, 69.65, 18.96), None);
+            assert_eq!(
+                schedule.at(&now),
+                ScheduleState {
+                    brightness: 1.0,
+                    temperature: 6500.0
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn neutral_temperature_only_applies_brightness() {
+        assert_eq!(ScheduleState::default().scale(0.8), [0.8; 3]);
+
+        let warm = ScheduleState {
+            brightness: 0.5,
+            temperature: 3000.0,
+        }
+        .scale(0.8);
+        assert_eq!(warm[0], 0.4);
+        assert!(warm[0] > warm[1] && warm[1] > warm[2]);
+    }
+}
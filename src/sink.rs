@@ -0,0 +1,1453 @@
+use flatbuffers::{FlatBufferBuilder, Follow, ForwardsUOffset, Table, Verifiable};
+use openrgb2::{Controller, OpenRgbClient, Zone};
+use rgb::RGB8;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    hash::{BuildHasher, Hasher, RandomState},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket, lookup_host, tcp::OwnedWriteHalf},
+    sync::{Mutex, watch},
+    task::JoinHandle,
+};
+use tokio_serial::SerialStream;
+
+use crate::{
+    SHUTDOWN_BLACK_REPEATS,
+    backoff::Backoff,
+    color::{Smoother, limit_current},
+    config::{DmxConfig, DmxProtocol, HyperionConfig, HyperionMode, WledProtocol},
+    reload::LiveSettings,
+};
+
+/// Most LEDs a single WARLS packet can address
+pub const WARLS_MAX_LEDS: usize = 255;
+/// Most LEDs WLED accepts in a DRGB packet
+pub const DRGB_MAX_LEDS: usize = 490;
+/// LEDs per DNRGB packet, keeps each packet within WLED's buffer
+const DNRGB_LEDS_PER_PACKET: usize = 489;
+
+const DDP_VERSION_1: u8 = 0x40;
+/// Tells the receiver to display the data received so far
+const DDP_PUSH: u8 = 0x01;
+const DDP_TYPE_RGB8: u8 = 0x0b;
+const DDP_ID_DISPLAY: u8 = 0x01;
+/// Bytes of pixel data per DDP packet, 480 RGB LEDs
+const DDP_MAX_DATA_LEN: usize = 1440;
+
+/// E1.31 options bit telling receivers the source is going away
+const E131_STREAM_TERMINATED: u8 = 0x40;
+
+/// Members of the Hyperion flatbuffer `Command` union
+const HYPERION_COLOR: u8 = 1;
+const HYPERION_IMAGE: u8 = 2;
+const HYPERION_CLEAR: u8 = 3;
+const HYPERION_REGISTER: u8 = 4;
+/// `ImageType` union member carrying packed RGB data
+const HYPERION_RAW_IMAGE: u8 = 1;
+
+pub type SinkResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// LED output device fed with the combined color buffer of all monitors
+pub trait LedSink: Send + 'static {
+    /// Short name used in log messages
+    fn name(&self) -> &'static str;
+
+    /// Sends one frame of colors, ordered as produced by `calculate_regions`
+    fn send(&mut self, colors: &[[u8; 3]]) -> impl Future<Output = SinkResult> + Send;
+
+    /// Called when the number of LEDs per monitor changes on reload
+    fn set_layout(&mut self, led_counts: &[usize]);
+
+    /// Turns all LEDs off
+    fn blank(&mut self) -> impl Future<Output = SinkResult> + Send;
+
+    /// Releases the device before exit
+    fn close(&mut self) -> impl Future<Output = SinkResult> + Send;
+
+    /// Whether the device is open, sinks without a connection always are
+    fn is_connected(&self) -> bool {
+        true
+    }
+
+    /// Opens the device, called while `is_connected` returns false
+    fn reconnect(&mut self) -> impl Future<Output = SinkResult> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Whether the last frame reached the device, shown in the control status
+pub struct SinkHealth {
+    pub name: &'static str,
+    ok: AtomicBool,
+    /// Estimated current of the last frame in mA, as `f32` bits
+    draw_ma: AtomicU32,
+}
+
+impl SinkHealth {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ok: AtomicBool::new(true),
+            draw_ma: AtomicU32::new(0),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.ok.load(Ordering::Relaxed)
+    }
+
+    pub fn draw_ma(&self) -> f32 {
+        f32::from_bits(self.draw_ma.load(Ordering::Relaxed))
+    }
+}
+
+/// Shared state every sink task reads from
+#[derive(Clone)]
+pub struct SinkInputs {
+    pub colors: Arc<Mutex<Vec<[u8; 3]>>>,
+    pub smoother: Arc<Mutex<Smoother>>,
+    pub live: watch::Receiver<Arc<LiveSettings>>,
+    pub manual_pause: Arc<AtomicBool>,
+    pub screen_off: Arc<AtomicBool>,
+    pub shutdown: Arc<AtomicBool>,
+}
+
+/// Starts a task driving `sink` as output `index` with the LEDs of `monitors`
+pub fn spawn_sink(
+    sink: impl LedSink,
+    index: usize,
+    monitors: Vec<usize>,
+    inputs: SinkInputs,
+) -> (Arc<SinkHealth>, JoinHandle<()>) {
+    let health = Arc::new(SinkHealth::new(sink.name()));
+    let handle = tokio::spawn(run_sink(sink, health.clone(), index, monitors, inputs));
+    (health, handle)
+}
+
+/// Drives `sink` from the shared color buffer until shutdown, then blanks it.
+///
+/// Every sink runs in its own task at its own pace, so a slow device doesn't
+/// hold back the others.
+async fn run_sink(
+    mut sink: impl LedSink,
+    health: Arc<SinkHealth>,
+    index: usize,
+    monitors: Vec<usize>,
+    mut inputs: SinkInputs,
+) {
+    let mut backoff = Backoff::new(Instant::now());
+    let mut applied_counts = Vec::new();
+
+    loop {
+        // One snapshot per iteration, so the layout always matches the frame
+        let live = inputs.live.borrow_and_update().clone();
+        let led_counts: Vec<usize> = monitors.iter().map(|&m| live.regions[m].len()).collect();
+        if led_counts != applied_counts {
+            sink.set_layout(&led_counts);
+            applied_counts = led_counts;
+        }
+        let delay_ms = live.delay_ms;
+
+        if inputs.shutdown.load(Ordering::Relaxed) {
+            for _ in 0..SHUTDOWN_BLACK_REPEATS {
+                let _ = sink.blank().await;
+            }
+            if let Err(e) = sink.close().await {
+                eprintln!("{} close error: {e}", sink.name());
+            }
+            break;
+        }
+
+        if !sink.is_connected() {
+            if backoff.is_due(Instant::now()) {
+                match sink.reconnect().await {
+                    Ok(()) => {
+                        println!("{} connected", sink.name());
+                        backoff.reset();
+                    }
+                    Err(e) => {
+                        let delay = backoff.retry_later(Instant::now());
+                        eprintln!(
+                            "{} connect error: {e}, retrying in {}s",
+                            sink.name(),
+                            delay.as_secs()
+                        );
+                    }
+                }
+            }
+            // Frames captured while the device is gone are dropped
+            if !sink.is_connected() {
+                health.ok.store(false, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                continue;
+            }
+        }
+
+        let is_paused = inputs.manual_pause.load(Ordering::Relaxed)
+            || inputs.screen_off.load(Ordering::Relaxed);
+
+        let res = if is_paused {
+            health.draw_ma.store(0, Ordering::Relaxed);
+            sink.blank().await
+        } else {
+            let colors = inputs.colors.lock().await;
+            // A reload resized the buffer after the snapshot was taken, the
+            // next iteration picks up the new settings. Waiting first keeps
+            // the task from spinning on the lock until it is published.
+            if colors.len() != live.total_leds() {
+                drop(colors);
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                continue;
+            }
+            let mut smoother = inputs.smoother.lock().await;
+            let smoothing = live.smooth.then_some(&live.smoothing);
+            let smoothed = smoother.update(&colors, &live.led_counts(), smoothing, Instant::now());
+            drop(smoother);
+            drop(colors);
+
+            let mut frame = map_frame(&smoothed, &live, &monitors);
+            live.corrections[index].apply(&mut frame);
+            if let Some(Some(limit)) = live.current_limits.get(index) {
+                let draw = limit_current(&mut frame, limit);
+                health.draw_ma.store(draw.to_bits(), Ordering::Relaxed);
+            }
+            sink.send(&frame).await
+        };
+        health.ok.store(res.is_ok(), Ordering::Relaxed);
+        if let Err(e) = res {
+            eprintln!("{} send error: {e}", sink.name());
+        }
+
+        tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    }
+}
+
+/// Colors of `monitors` in the given order, cut out of the combined buffer
+fn map_frame(colors: &[[u8; 3]], live: &LiveSettings, monitors: &[usize]) -> Vec<[u8; 3]> {
+    let mut frame = Vec::with_capacity(colors.len());
+    for &m in monitors {
+        let offset = live.offset(m);
+        let len = live.regions[m].len();
+        match colors.get(offset..offset + len) {
+            Some(leds) => frame.extend_from_slice(leds),
+            // Only while a reload changes the LED count
+            None => frame.resize(frame.len() + len, [0; 3]),
+        }
+    }
+    frame
+}
+
+pub struct SerialSink {
+    path: String,
+    baud_rate: u32,
+    /// `None` until opened and after the device went away
+    port: Option<SerialStream>,
+    header: &'static [u8; 3],
+    total_leds: usize,
+}
+
+impl SerialSink {
+    /// The port is opened by the sink task, so a missing device is retried
+    pub fn new(path: &str, baud_rate: u32, header: &'static [u8; 3], total_leds: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            baud_rate,
+            port: None,
+            header,
+            total_leds,
+        }
+    }
+
+    async fn write(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        let port = self.port.as_mut().ok_or("serial port is not open")?;
+        let res = send_frame(port, colors, self.header).await;
+        if res.is_err() {
+            self.port = None;
+        }
+        res
+    }
+}
+
+impl LedSink for SerialSink {
+    fn name(&self) -> &'static str {
+        "Serial"
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        self.write(colors).await
+    }
+
+    fn set_layout(&mut self, led_counts: &[usize]) {
+        self.total_leds = led_counts.iter().sum();
+    }
+
+    async fn blank(&mut self) -> SinkResult {
+        let black = vec![[0u8; 3]; self.total_leds];
+        self.write(&black).await
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        if let Some(port) = &mut self.port {
+            port.flush().await?;
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.port.is_some()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        let port = SerialStream::open(&tokio_serial::new(&self.path, self.baud_rate))?;
+        self.port = Some(port);
+        Ok(())
+    }
+}
+
+/// OpenRGB zone fed by one monitor's slice of the combined buffer
+pub struct ZoneSlot {
+    pub device_id: usize,
+    pub zone_id: usize,
+    pub offset: usize,
+    pub len: usize,
+}
+
+pub struct OpenRgbSink {
+    /// Empty until connected and after the server went away
+    controllers: HashMap<usize, Controller>,
+    zones: Vec<ZoneSlot>,
+}
+
+impl OpenRgbSink {
+    /// The server is connected by the sink task, so a missing one is retried
+    pub fn new(zones: Vec<ZoneSlot>) -> Self {
+        Self {
+            controllers: HashMap::new(),
+            zones,
+        }
+    }
+
+    fn zone(&self, slot: &ZoneSlot) -> Result<Zone<'_>, Box<dyn std::error::Error + Send + Sync>> {
+        let controller = self
+            .controllers
+            .get(&slot.device_id)
+            .ok_or("OpenRGB is not connected")?;
+        Ok(controller.get_zone(slot.zone_id)?)
+    }
+
+    /// Drops the connection if `res` failed, so the sink task reconnects
+    fn check(&mut self, res: SinkResult) -> SinkResult {
+        if res.is_err() {
+            self.controllers.clear();
+        }
+        res
+    }
+}
+
+impl LedSink for OpenRgbSink {
+    fn name(&self) -> &'static str {
+        "OpenRGB"
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        let mut res = Ok(());
+        for slot in &self.zones {
+            let Some(leds) = colors.get(slot.offset..slot.offset + slot.len) else {
+                return Err(format!(
+                    "Frame of {} LEDs ends before zone {} of device {}",
+                    colors.len(),
+                    slot.zone_id,
+                    slot.device_id
+                )
+                .into());
+            };
+            res = match self.zone(slot) {
+                Ok(zone) => send_data(&zone, leds).await,
+                Err(e) => Err(e),
+            };
+            if res.is_err() {
+                break;
+            }
+        }
+        self.check(res)
+    }
+
+    fn set_layout(&mut self, led_counts: &[usize]) {
+        let mut offset = 0;
+        for (slot, &len) in self.zones.iter_mut().zip(led_counts) {
+            slot.offset = offset;
+            slot.len = len;
+            offset += len;
+        }
+    }
+
+    async fn blank(&mut self) -> SinkResult {
+        let mut res = Ok(());
+        for slot in &self.zones {
+            res = match self.zone(slot) {
+                Ok(zone) => zone
+                    .set_all_leds(RGB8::new(0, 0, 0))
+                    .await
+                    .map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            if res.is_err() {
+                break;
+            }
+        }
+        self.check(res)
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        !self.controllers.is_empty()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        let client = OpenRgbClient::connect().await?;
+        let mut controllers = HashMap::new();
+        for slot in &self.zones {
+            let controller = match controllers.entry(slot.device_id) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(client.get_controller(slot.device_id).await?),
+            };
+            controller.get_zone(slot.zone_id)?;
+        }
+        self.controllers = controllers;
+        Ok(())
+    }
+}
+
+/// WLED controller driven over its realtime UDP protocols
+pub struct WledSink {
+    host: String,
+    port: u16,
+    /// `None` until the host resolved and after a send failed
+    socket: Option<UdpSocket>,
+    protocol: WledProtocol,
+    timeout: u8,
+    total_leds: usize,
+}
+
+impl WledSink {
+    /// The host is resolved by the sink task, so one that isn't up yet is retried
+    pub fn new(
+        host: &str,
+        port: u16,
+        protocol: WledProtocol,
+        timeout: u8,
+        total_leds: usize,
+    ) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            socket: None,
+            protocol,
+            timeout,
+            total_leds,
+        }
+    }
+}
+
+impl LedSink for WledSink {
+    fn name(&self) -> &'static str {
+        "WLED"
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        let socket = self.socket.as_ref().ok_or("WLED is not connected")?;
+        for packet in prepare_wled_packets(colors, self.protocol, self.timeout) {
+            if let Err(e) = socket.send(&packet).await {
+                self.socket = None;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn set_layout(&mut self, led_counts: &[usize]) {
+        self.total_leds = led_counts.iter().sum();
+    }
+
+    async fn blank(&mut self) -> SinkResult {
+        let black = vec![[0u8; 3]; self.total_leds];
+        self.send(&black).await
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        self.socket = Some(connected_udp_socket(&self.host, self.port).await?);
+        Ok(())
+    }
+}
+
+/// E1.31 (sACN) or Art-Net node, one packet per universe
+pub struct DmxSink {
+    /// Unicast receiver name, `None` for E1.31 multicast or Art-Net broadcast
+    host_name: Option<String>,
+    /// `None` until the host resolved and after a send failed
+    socket: Option<UdpSocket>,
+    protocol: DmxProtocol,
+    /// Resolved unicast receiver
+    host: Option<SocketAddr>,
+    port: u16,
+    start_universe: u16,
+    leds_per_universe: usize,
+    priority: u8,
+    sequence: bool,
+    /// Last sequence number sent to each universe
+    counters: Vec<u8>,
+    /// E1.31 component identifier, unique per run
+    cid: [u8; 16],
+    total_leds: usize,
+}
+
+impl DmxSink {
+    /// The socket is opened by the sink task, so a host that isn't up yet is retried
+    pub fn new(config: &DmxConfig, total_leds: usize) -> Self {
+        let mut cid = [0u8; 16];
+        for half in cid.chunks_mut(8) {
+            half.copy_from_slice(&RandomState::new().build_hasher().finish().to_be_bytes());
+        }
+
+        Self {
+            host_name: config.host.clone(),
+            socket: None,
+            protocol: config.protocol,
+            host: None,
+            port: config.port(),
+            start_universe: config.universe,
+            leds_per_universe: config.universe_size / 3,
+            priority: config.priority,
+            sequence: config.sequence,
+            counters: Vec::new(),
+            cid,
+            total_leds,
+        }
+    }
+
+    fn destination(&self, universe: u16) -> SocketAddr {
+        match (self.host, self.protocol) {
+            (Some(addr), _) => addr,
+            (None, DmxProtocol::E131) => {
+                let [hi, lo] = universe.to_be_bytes();
+                SocketAddr::from((Ipv4Addr::new(239, 255, hi, lo), self.port))
+            }
+            (None, DmxProtocol::Artnet) => SocketAddr::from((Ipv4Addr::BROADCAST, self.port)),
+        }
+    }
+
+    fn next_sequence(&mut self, index: usize) -> u8 {
+        if !self.sequence {
+            return 0;
+        }
+        if self.counters.len() <= index {
+            self.counters.resize(index + 1, 0);
+        }
+        let counter = &mut self.counters[index];
+        *counter = counter.wrapping_add(1);
+        // Art-Net reserves 0 for "sequencing disabled"
+        if *counter == 0 && self.protocol == DmxProtocol::Artnet {
+            *counter = 1;
+        }
+        *counter
+    }
+
+    async fn send_universes(&mut self, colors: &[[u8; 3]], options: u8) -> SinkResult {
+        for (index, leds) in colors.chunks(self.leds_per_universe).enumerate() {
+            let universe = self.start_universe + index as u16;
+            let sequence = self.next_sequence(index);
+            let packet = prepare_dmx_packet(
+                self.protocol,
+                universe,
+                sequence,
+                self.priority,
+                options,
+                &self.cid,
+                leds,
+            );
+            let destination = self.destination(universe);
+            let Some(socket) = &self.socket else {
+                return Err(format!("{} socket is not open", self.name()).into());
+            };
+            if let Err(e) = socket.send_to(&packet, destination).await {
+                self.socket = None;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LedSink for DmxSink {
+    fn name(&self) -> &'static str {
+        match self.protocol {
+            DmxProtocol::E131 => "E1.31",
+            DmxProtocol::Artnet => "Art-Net",
+        }
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        self.send_universes(colors, 0).await
+    }
+
+    fn set_layout(&mut self, led_counts: &[usize]) {
+        self.total_leds = led_counts.iter().sum();
+    }
+
+    async fn blank(&mut self) -> SinkResult {
+        let black = vec![[0u8; 3]; self.total_leds];
+        self.send_universes(&black, 0).await
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        // E1.31 receivers drop the source right away instead of waiting for
+        // the 2.5s data loss timeout, the spec asks for three packets
+        if self.protocol == DmxProtocol::E131 {
+            let black = vec![[0u8; 3]; self.total_leds];
+            for _ in 0..3 {
+                self.send_universes(&black, E131_STREAM_TERMINATED).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        let (socket, host) = match &self.host_name {
+            Some(host) => {
+                let (socket, addr) = udp_socket_for(host, self.port).await?;
+                (socket, Some(addr))
+            }
+            None => (UdpSocket::bind("0.0.0.0:0").await?, None),
+        };
+        if host.is_none() && self.protocol == DmxProtocol::Artnet {
+            socket.set_broadcast(true)?;
+        }
+        self.socket = Some(socket);
+        self.host = host;
+        Ok(())
+    }
+}
+
+/// DDP receiver such as WLED, xLights or ESPixelStick
+pub struct DdpSink {
+    host: String,
+    port: u16,
+    /// `None` until the host resolved and after a send failed
+    socket: Option<UdpSocket>,
+    /// Cycles through 1 to 15, 0 would mean "not used"
+    sequence: u8,
+    total_leds: usize,
+}
+
+impl DdpSink {
+    /// The host is resolved by the sink task, so one that isn't up yet is retried
+    pub fn new(host: &str, port: u16, total_leds: usize) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            socket: None,
+            sequence: 0,
+            total_leds,
+        }
+    }
+}
+
+impl LedSink for DdpSink {
+    fn name(&self) -> &'static str {
+        "DDP"
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        let socket = self.socket.as_ref().ok_or("DDP is not connected")?;
+        self.sequence = self.sequence % 15 + 1;
+        for packet in prepare_ddp_frame(colors, self.sequence) {
+            if let Err(e) = socket.send(&packet).await {
+                self.socket = None;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn set_layout(&mut self, led_counts: &[usize]) {
+        self.total_leds = led_counts.iter().sum();
+    }
+
+    async fn blank(&mut self) -> SinkResult {
+        let black = vec![[0u8; 3]; self.total_leds];
+        self.send(&black).await
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        self.socket = Some(connected_udp_socket(&self.host, self.port).await?);
+        Ok(())
+    }
+}
+
+/// Hyperion or HyperHDR flatbuffer server, ambiway shows up as one source
+pub struct HyperionSink {
+    config: HyperionConfig,
+    /// `None` until registered and after the connection dropped
+    stream: Option<OwnedWriteHalf>,
+    /// Whether our priority is already cleared, so a pause sends one clear
+    cleared: bool,
+}
+
+impl HyperionSink {
+    /// The server is connected by the sink task, so a missing one is retried
+    pub fn new(config: &HyperionConfig) -> Self {
+        Self {
+            config: config.clone(),
+            stream: None,
+            cleared: false,
+        }
+    }
+
+    async fn write(&mut self, command: &HyperionCommand<'_>) -> SinkResult {
+        let stream = self.stream.as_mut().ok_or("Hyperion is not connected")?;
+        let res = stream.write_all(&prepare_hyperion_message(command)).await;
+        if res.is_err() {
+            self.stream = None;
+        }
+        Ok(res?)
+    }
+}
+
+impl LedSink for HyperionSink {
+    fn name(&self) -> &'static str {
+        "Hyperion"
+    }
+
+    async fn send(&mut self, colors: &[[u8; 3]]) -> SinkResult {
+        if colors.is_empty() {
+            return Ok(());
+        }
+        let command = match self.config.mode {
+            HyperionMode::Image => HyperionCommand::Image(colors),
+            HyperionMode::Color => HyperionCommand::Color(mean_color(colors)),
+        };
+        self.write(&command).await?;
+        self.cleared = false;
+        Ok(())
+    }
+
+    fn set_layout(&mut self, _led_counts: &[usize]) {}
+
+    async fn blank(&mut self) -> SinkResult {
+        // Hand the LEDs back to lower priority sources instead of painting
+        // them black
+        if !self.cleared {
+            let clear = HyperionCommand::Clear {
+                priority: self.config.priority,
+            };
+            self.write(&clear).await?;
+            self.cleared = true;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> SinkResult {
+        if self.stream.is_some() {
+            self.blank().await?;
+        }
+        if let Some(stream) = &mut self.stream {
+            stream.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    async fn reconnect(&mut self) -> SinkResult {
+        let config = &self.config;
+        let mut stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+        stream.set_nodelay(true)?;
+
+        let register = HyperionCommand::Register {
+            origin: &config.origin,
+            priority: config.priority,
+        };
+        stream
+            .write_all(&prepare_hyperion_message(&register))
+            .await?;
+        if let Some(error) = read_hyperion_reply(&mut stream).await? {
+            return Err(format!("Hyperion refused registration: {error}").into());
+        }
+
+        // The server answers every command, drain the replies so they don't
+        // pile up and report the errors among them
+        let (mut reader, writer) = stream.into_split();
+        tokio::spawn(async move {
+            while let Ok(reply) = read_hyperion_reply(&mut reader).await {
+                if let Some(error) = reply {
+                    eprintln!("Hyperion error: {error}");
+                }
+            }
+        });
+
+        self.stream = Some(writer);
+        self.cleared = false;
+        Ok(())
+    }
+}
+
+/// UDP socket sending to `host`, which is resolved once per call
+async fn connected_udp_socket(host: &str, port: u16) -> std::io::Result<UdpSocket> {
+    let (socket, addr) = udp_socket_for(host, port).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+/// Resolves `host` and binds a UDP socket of the matching address family
+async fn udp_socket_for(host: &str, port: u16) -> std::io::Result<(UdpSocket, SocketAddr)> {
+    let addr = lookup_host((host, port)).await?.next().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, format!("{host} not found"))
+    })?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    Ok((UdpSocket::bind(local).await?, addr))
+}
+
+async fn send_data<'a>(zone: &Zone<'a>, data: &[[u8; 3]]) -> SinkResult {
+    let colors: Vec<RGB8> = data
+        .iter()
+        .map(|rgb| RGB8::new(rgb[0], rgb[1], rgb[2]))
+        .collect();
+
+    // Send data
+    zone.set_leds(colors).await?;
+
+    Ok(())
+}
+
+fn mean_color(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0usize; 3];
+    for color in colors {
+        for (s, &c) in sum.iter_mut().zip(color) {
+            *s += c as usize;
+        }
+    }
+    sum.map(|s| (s / colors.len().max(1)) as u8)
+}
+
+fn prepare_serial_frame(colors: &[[u8; 3]], header: &[u8; 3]) -> Vec<u8> {
+    let num_leds = colors.len();
+    let count = num_leds.wrapping_sub(1);
+    let hi = (count >> 8) as u8;
+    let lo = count as u8;
+    let checksum = hi ^ lo ^ 0x55;
+
+    let mut buffer = Vec::with_capacity(6 + num_leds * 3 + 3);
+
+    buffer.extend_from_slice(header);
+    buffer.push(hi);
+    buffer.push(lo);
+    buffer.push(checksum);
+
+    for color in colors {
+        buffer.extend_from_slice(color);
+    }
+
+    let mut f1: u16 = 0;
+    let mut f2: u16 = 0;
+    let mut fext: u16 = 0;
+    for (pos, &byte) in buffer[6..].iter().enumerate() {
+        f1 = (f1 + byte as u16) % 255;
+        f2 = (f2 + f1) % 255;
+        fext = (fext + (byte as u16 ^ (pos & 0xff) as u16)) % 255;
+    }
+    if fext == 0x41 {
+        fext = 0xaa;
+    }
+    buffer.push(f1 as u8);
+    buffer.push(f2 as u8);
+    buffer.push(fext as u8);
+
+    buffer
+}
+
+/// Splits one frame into WLED realtime packets.
+///
+/// Every packet starts with the protocol byte and the timeout byte. WARLS
+/// sends an index before each color, DRGB the plain colors, and DNRGB a
+/// big-endian start index followed by up to `DNRGB_LEDS_PER_PACKET` colors.
+fn prepare_wled_packets(colors: &[[u8; 3]], protocol: WledProtocol, timeout: u8) -> Vec<Vec<u8>> {
+    let chunked = match protocol {
+        WledProtocol::Warls => {
+            let leds = &colors[..colors.len().min(WARLS_MAX_LEDS)];
+            let mut packet = Vec::with_capacity(2 + leds.len() * 4);
+            packet.extend_from_slice(&[1, timeout]);
+            for (i, color) in leds.iter().enumerate() {
+                packet.push(i as u8);
+                packet.extend_from_slice(color);
+            }
+            return vec![packet];
+        }
+        WledProtocol::Drgb => false,
+        WledProtocol::Dnrgb => true,
+        WledProtocol::Auto => colors.len() > DRGB_MAX_LEDS,
+    };
+
+    if !chunked {
+        let leds = &colors[..colors.len().min(DRGB_MAX_LEDS)];
+        let mut packet = Vec::with_capacity(2 + leds.len() * 3);
+        packet.extend_from_slice(&[2, timeout]);
+        packet.extend(leds.iter().flatten());
+        return vec![packet];
+    }
+
+    colors
+        .chunks(DNRGB_LEDS_PER_PACKET)
+        .enumerate()
+        .map(|(i, leds)| {
+            let start = (i * DNRGB_LEDS_PER_PACKET) as u16;
+            let mut packet = Vec::with_capacity(4 + leds.len() * 3);
+            packet.extend_from_slice(&[4, timeout]);
+            packet.extend_from_slice(&start.to_be_bytes());
+            packet.extend(leds.iter().flatten());
+            packet
+        })
+        .collect()
+}
+
+/// Builds an E1.31 data packet or an ArtDmx packet carrying `leds` as
+/// consecutive RGB channels of `universe`. `priority`, `options` and `cid`
+/// are only used by E1.31.
+fn prepare_dmx_packet(
+    protocol: DmxProtocol,
+    universe: u16,
+    sequence: u8,
+    priority: u8,
+    options: u8,
+    cid: &[u8; 16],
+    leds: &[[u8; 3]],
+) -> Vec<u8> {
+    let channels = leds.len() * 3;
+
+    match protocol {
+        DmxProtocol::E131 => {
+            let len = 126 + channels;
+            let flags_len = |from: usize| (0x7000 | (len - from) as u16).to_be_bytes();
+            let mut source_name = [0u8; 64];
+            source_name[..7].copy_from_slice(b"ambiway");
+
+            let mut packet = Vec::with_capacity(len);
+            // Root layer
+            packet.extend_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+            packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+            packet.extend_from_slice(&flags_len(16));
+            packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x04]);
+            packet.extend_from_slice(cid);
+            // Framing layer
+            packet.extend_from_slice(&flags_len(38));
+            packet.extend_from_slice(&[0x00, 0x00, 0x00, 0x02]);
+            packet.extend_from_slice(&source_name);
+            packet.push(priority);
+            packet.extend_from_slice(&[0x00, 0x00]);
+            packet.push(sequence);
+            packet.push(options);
+            packet.extend_from_slice(&universe.to_be_bytes());
+            // DMP layer, the DMX start code counts as the first property
+            packet.extend_from_slice(&flags_len(115));
+            packet.extend_from_slice(&[0x02, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+            packet.extend_from_slice(&(channels as u16 + 1).to_be_bytes());
+            packet.push(0x00);
+            packet.extend(leds.iter().flatten());
+            packet
+        }
+        DmxProtocol::Artnet => {
+            // ArtDmx data length must be even
+            let data_len = channels.max(2).next_multiple_of(2);
+            let mut packet = Vec::with_capacity(18 + data_len);
+            packet.extend_from_slice(b"Art-Net\0");
+            packet.extend_from_slice(&0x5000u16.to_le_bytes());
+            packet.extend_from_slice(&14u16.to_be_bytes());
+            packet.push(sequence);
+            packet.push(0);
+            packet.extend_from_slice(&(universe & 0x7fff).to_le_bytes());
+            packet.extend_from_slice(&(data_len as u16).to_be_bytes());
+            packet.extend(leds.iter().flatten());
+            packet.resize(18 + data_len, 0);
+            packet
+        }
+    }
+}
+
+/// Splits one frame into DDP packets.
+///
+/// Each packet has a 10-byte header: flags (version 1, push on the last
+/// packet), sequence, data type (8-bit RGB), destination (default output),
+/// byte offset of its data in the frame and data length.
+fn prepare_ddp_frame(colors: &[[u8; 3]], sequence: u8) -> Vec<Vec<u8>> {
+    let data: Vec<u8> = colors.iter().flatten().copied().collect();
+    // Keep an empty frame as one packet so the push still happens
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(DDP_MAX_DATA_LEN).collect()
+    };
+    let last = chunks.len() - 1;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let flags = if i == last {
+                DDP_VERSION_1 | DDP_PUSH
+            } else {
+                DDP_VERSION_1
+            };
+            let offset = (i * DDP_MAX_DATA_LEN) as u32;
+            let mut packet = Vec::with_capacity(10 + chunk.len());
+            packet.extend_from_slice(&[flags, sequence, DDP_TYPE_RGB8, DDP_ID_DISPLAY]);
+            packet.extend_from_slice(&offset.to_be_bytes());
+            packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            packet
+        })
+        .collect()
+}
+
+/// Command of the Hyperion flatbuffer protocol
+enum HyperionCommand<'a> {
+    Register { origin: &'a str, priority: i32 },
+    Image(&'a [[u8; 3]]),
+    Color([u8; 3]),
+    Clear { priority: i32 },
+}
+
+/// Encodes a Hyperion `Request` table holding `command`.
+///
+/// Every message is prefixed with its big-endian 32-bit length. Images are
+/// sent as a single row with one pixel per LED, which Hyperion samples with
+/// its own LED layout, so only a layout of one row maps each pixel to its LED.
+/// Durations are left at -1 so colors stay until cleared.
+fn prepare_hyperion_message(command: &HyperionCommand) -> Vec<u8> {
+    let mut fbb = FlatBufferBuilder::new();
+
+    let (command_type, command) = match *command {
+        HyperionCommand::Register { origin, priority } => {
+            let origin = fbb.create_string(origin);
+            let start = fbb.start_table();
+            fbb.push_slot_always(4, origin);
+            fbb.push_slot::<i32>(6, priority, 0);
+            (HYPERION_REGISTER, fbb.end_table(start))
+        }
+        HyperionCommand::Image(colors) => {
+            let data: Vec<u8> = colors.iter().flatten().copied().collect();
+            let data = fbb.create_vector(&data);
+            let start = fbb.start_table();
+            fbb.push_slot_always(4, data);
+            fbb.push_slot::<i32>(6, colors.len() as i32, -1);
+            fbb.push_slot::<i32>(8, 1, -1);
+            let raw_image = fbb.end_table(start);
+
+            let start = fbb.start_table();
+            fbb.push_slot_always(6, raw_image);
+            fbb.push_slot::<u8>(4, HYPERION_RAW_IMAGE, 0);
+            (HYPERION_IMAGE, fbb.end_table(start))
+        }
+        HyperionCommand::Color([r, g, b]) => {
+            let rgb = i32::from_be_bytes([0, r, g, b]);
+            let start = fbb.start_table();
+            // The schema default is -1 (white), so black has to be written too
+            fbb.push_slot_always::<i32>(4, rgb);
+            (HYPERION_COLOR, fbb.end_table(start))
+        }
+        HyperionCommand::Clear { priority } => {
+            let start = fbb.start_table();
+            fbb.push_slot::<i32>(4, priority, 0);
+            (HYPERION_CLEAR, fbb.end_table(start))
+        }
+    };
+
+    let start = fbb.start_table();
+    fbb.push_slot_always(6, command);
+    fbb.push_slot::<u8>(4, command_type, 0);
+    let request = fbb.end_table(start);
+    fbb.finish_minimal(request);
+
+    let data = fbb.finished_data();
+    let mut message = Vec::with_capacity(4 + data.len());
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Hyperion `Reply` table, only its `error` field is of interest
+struct HyperionReply<'a>(Table<'a>);
+
+impl<'a> Follow<'a> for HyperionReply<'a> {
+    type Inner = Self;
+
+    unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self(unsafe { Table::new(buf, loc) })
+    }
+}
+
+impl Verifiable for HyperionReply<'_> {
+    fn run_verifier(
+        v: &mut flatbuffers::Verifier,
+        pos: usize,
+    ) -> Result<(), flatbuffers::InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<ForwardsUOffset<&str>>("error", 4, false)?
+            .visit_field::<i32>("video", 6, false)?
+            .visit_field::<i32>("registered", 8, false)?
+            .finish();
+        Ok(())
+    }
+}
+
+/// Reads one length-prefixed reply, returning the error it reports if any
+async fn read_hyperion_reply(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> std::io::Result<Option<String>> {
+    let len = stream.read_u32().await? as usize;
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await?;
+
+    let reply = flatbuffers::root::<HyperionReply>(&data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    // SAFETY: the verifier checked that field 4 is a string
+    let error = unsafe { reply.0.get::<ForwardsUOffset<&str>>(4, None) };
+    Ok(error.filter(|e| !e.is_empty()).map(str::to_string))
+}
+
+async fn send_frame(port: &mut SerialStream, colors: &[[u8; 3]], header: &[u8; 3]) -> SinkResult {
+    // 3-byte handshake prefix (triggers HyperHDR/Rp2040 handshake)
+    port.write_all(&[0x00, 0x00, 0x00]).await?;
+
+    let buffer = prepare_serial_frame(colors, header);
+    port.write_all(&buffer).await?;
+    port.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Colors whose bytes tell the LED index apart
+    fn gradient(len: usize) -> Vec<[u8; 3]> {
+        (0..len).map(|i| [i as u8, (i >> 8) as u8, 0xee]).collect()
+    }
+
+    #[tokio::test]
+    async fn wled_packets_reach_a_udp_receiver() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let mut buf = vec![0u8; 2048];
+        let mut receive = async || {
+            let len = tokio::time::timeout(Duration::from_secs(5), receiver.recv(&mut buf))
+                .await
+                .expect("no packet received")
+                .unwrap();
+            buf[..len].to_vec()
+        };
+
+        let mut sink = WledSink::new("127.0.0.1", port, WledProtocol::Warls, 3, 2);
+        sink.reconnect().await.unwrap();
+        sink.send(&gradient(2)).await.unwrap();
+        assert_eq!(receive().await, [1, 3, 0, 0, 0, 0xee, 1, 1, 0, 0xee]);
+
+        let mut sink = WledSink::new("127.0.0.1", port, WledProtocol::Auto, 255, 2);
+        sink.reconnect().await.unwrap();
+        sink.send(&gradient(2)).await.unwrap();
+        assert_eq!(receive().await, [2, 255, 0, 0, 0xee, 1, 0, 0xee]);
+
+        // Auto switches to DNRGB above DRGB_MAX_LEDS
+        let colors = gradient(DRGB_MAX_LEDS + 10);
+        sink.send(&colors).await.unwrap();
+        let first = receive().await;
+        let second = receive().await;
+        assert_eq!(first[..4], [4, 255, 0, 0]);
+        assert_eq!(first.len(), 4 + DNRGB_LEDS_PER_PACKET * 3);
+        assert_eq!(first[4..7], colors[0]);
+        let start = (DNRGB_LEDS_PER_PACKET as u16).to_be_bytes();
+        assert_eq!(second[..4], [4, 255, start[0], start[1]]);
+        assert_eq!(second.len(), 4 + (colors.len() - DNRGB_LEDS_PER_PACKET) * 3);
+        assert_eq!(second[4..7], colors[DNRGB_LEDS_PER_PACKET]);
+    }
+
+    #[test]
+    fn e131_packet_layout() {
+        let cid: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let packet = prepare_dmx_packet(
+            DmxProtocol::E131,
+            0x0203,
+            7,
+            100,
+            E131_STREAM_TERMINATED,
+            &cid,
+            &[[1, 2, 3], [4, 5, 6]],
+        );
+        assert_eq!(packet.len(), 126 + 6);
+        // Root layer, its length counts from offset 16
+        assert_eq!(packet[..16], *b"\0\x10\0\0ASC-E1.17\0\0\0");
+        assert_eq!(packet[16..18], [0x70, 132 - 16]);
+        assert_eq!(packet[18..22], [0, 0, 0, 4]);
+        assert_eq!(packet[22..38], cid);
+        // Framing layer
+        assert_eq!(packet[38..40], [0x70, 132 - 38]);
+        assert_eq!(packet[40..44], [0, 0, 0, 2]);
+        assert_eq!(packet[44..52], *b"ambiway\0");
+        assert_eq!(packet[108], 100);
+        assert_eq!(packet[111], 7);
+        assert_eq!(packet[112], E131_STREAM_TERMINATED);
+        assert_eq!(packet[113..115], [0x02, 0x03]);
+        // DMP layer, the start code counts as the first property
+        assert_eq!(packet[115..117], [0x70, 132 - 115]);
+        assert_eq!(packet[117..123], [0x02, 0xa1, 0, 0, 0, 1]);
+        assert_eq!(packet[123..125], [0, 7]);
+        assert_eq!(packet[125], 0);
+        assert_eq!(packet[126..], [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn art_net_packet_layout() {
+        let packet = prepare_dmx_packet(
+            DmxProtocol::Artnet,
+            0x0203,
+            9,
+            100,
+            0,
+            &[0; 16],
+            &[[1, 2, 3], [4, 5, 6], [7, 8, 9]],
+        );
+        assert_eq!(packet[..8], *b"Art-Net\0");
+        // OpDmx is little-endian, the protocol version big-endian
+        assert_eq!(packet[8..10], [0x00, 0x50]);
+        assert_eq!(packet[10..12], [0, 14]);
+        assert_eq!(packet[12], 9);
+        assert_eq!(packet[13], 0);
+        // Port-Address is little-endian
+        assert_eq!(packet[14..16], [0x03, 0x02]);
+        // 9 channels padded to an even length
+        assert_eq!(packet[16..18], [0, 10]);
+        assert_eq!(packet[18..], [1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
+    }
+
+    #[test]
+    fn art_net_sequence_skips_zero() {
+        let config = |protocol| DmxConfig {
+            protocol,
+            host: None,
+            port: None,
+            universe: 1,
+            universe_size: 510,
+            priority: 100,
+            sequence: true,
+        };
+        let mut e131 = DmxSink::new(&config(DmxProtocol::E131), 1);
+        let mut artnet = DmxSink::new(&config(DmxProtocol::Artnet), 1);
+        for _ in 0..255 {
+            e131.next_sequence(0);
+            artnet.next_sequence(0);
+        }
+        assert_eq!(e131.next_sequence(0), 0);
+        assert_eq!(artnet.next_sequence(0), 1);
+    }
+
+    #[test]
+    fn ddp_frame_is_split_into_pushed_fragments() {
+        // 3 packets of 480, 480 and 40 LEDs
+        let colors = gradient(1000);
+        let packets = prepare_ddp_frame(&colors, 5);
+        assert_eq!(packets.len(), 3);
+        let data: Vec<u8> = colors.iter().flatten().copied().collect();
+        for (i, (packet, len)) in packets.iter().zip([1440u16, 1440, 120]).enumerate() {
+            let offset = (i * DDP_MAX_DATA_LEN) as u32;
+            let flags = if i == 2 { 0x41 } else { 0x40 };
+            let mut header = vec![flags, 5, 0x0b, 0x01];
+            header.extend_from_slice(&offset.to_be_bytes());
+            header.extend_from_slice(&len.to_be_bytes());
+            assert_eq!(packet[..10], header, "packet {i}");
+            assert_eq!(packet[10..], data[offset as usize..][..len as usize]);
+        }
+    }
+
+    #[tokio::test]
+    async fn ddp_sequence_cycles_through_1_to_15() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let mut sink = DdpSink::new("127.0.0.1", port, 1);
+        sink.reconnect().await.unwrap();
+
+        let mut sequences = Vec::new();
+        let mut buf = [0u8; 64];
+        for _ in 0..17 {
+            sink.send(&gradient(1)).await.unwrap();
+            let len = tokio::time::timeout(Duration::from_secs(5), receiver.recv(&mut buf))
+                .await
+                .expect("no packet received")
+                .unwrap();
+            assert_eq!(len, 13);
+            sequences.push(buf[1]);
+        }
+        let expected: Vec<u8> = (1..=15).chain([1, 2]).collect();
+        assert_eq!(sequences, expected);
+    }
+
+    /// Hyperion server accepting one client, returns every message it sent
+    /// until it disconnected, the registration first
+    fn mock_hyperion(listener: TcpListener) -> tokio::task::JoinHandle<Vec<Vec<u8>>> {
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut messages = Vec::new();
+            while let Ok(len) = stream.read_u32().await {
+                let mut data = vec![0u8; len as usize];
+                stream.read_exact(&mut data).await.unwrap();
+                messages.push(data);
+                // Reply without an error, the first reply accepts the registration
+                let mut fbb = FlatBufferBuilder::new();
+                let start = fbb.start_table();
+                let reply = fbb.end_table(start);
+                fbb.finish_minimal(reply);
+                let data = fbb.finished_data();
+                stream.write_u32(data.len() as u32).await.unwrap();
+                stream.write_all(data).await.unwrap();
+            }
+            messages
+        })
+    }
+
+    /// Sends `frames` through a `mode` sink and closes it, returns the messages
+    /// the server received
+    async fn run_hyperion(mode: HyperionMode, frames: &[&[[u8; 3]]]) -> Vec<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = HyperionConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            origin: "ambiway".to_string(),
+            priority: 150,
+            mode,
+        };
+        let server = mock_hyperion(listener);
+
+        let mut sink = HyperionSink::new(&config);
+        sink.reconnect().await.unwrap();
+        for frame in frames {
+            sink.send(frame).await.unwrap();
+        }
+        sink.close().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Command type and table of a `Request` message
+    fn command(data: &[u8]) -> (u8, Table<'_>) {
+        // SAFETY: written by prepare_hyperion_message with the Request layout
+        // read here
+        unsafe {
+            let request = flatbuffers::root_unchecked::<Table>(data);
+            let command_type = request.get::<u8>(4, Some(0)).unwrap();
+            let command = request.get::<ForwardsUOffset<Table>>(6, None).unwrap();
+            (command_type, command)
+        }
+    }
+
+    /// Checks the registration and the clear on shutdown around the commands
+    fn commands(messages: &[Vec<u8>]) -> Vec<(u8, Table<'_>)> {
+        let [register, commands @ .., clear] = messages else {
+            panic!("expected a registration and a clear, got {messages:?}");
+        };
+
+        let (command_type, register) = command(register);
+        assert_eq!(command_type, HYPERION_REGISTER);
+        // SAFETY: the Register table has the origin string at 4 and priority at 6
+        unsafe {
+            assert_eq!(
+                register.get::<ForwardsUOffset<&str>>(4, None),
+                Some("ambiway")
+            );
+            assert_eq!(register.get::<i32>(6, Some(0)), Some(150));
+        }
+
+        let (command_type, clear) = command(clear);
+        assert_eq!(command_type, HYPERION_CLEAR);
+        // SAFETY: the Clear table has the priority at 4
+        assert_eq!(unsafe { clear.get::<i32>(4, Some(0)) }, Some(150));
+
+        commands.iter().map(|m| command(m)).collect()
+    }
+
+    #[tokio::test]
+    async fn hyperion_color_mode_sends_black() {
+        let black = [[0, 0, 0]; 3];
+        let red = [[255, 0, 0]; 2];
+        let messages = run_hyperion(HyperionMode::Color, &[&black, &red]).await;
+        let colors: Vec<i32> = commands(&messages)
+            .into_iter()
+            .map(|(command_type, color)| {
+                assert_eq!(command_type, HYPERION_COLOR);
+                // SAFETY: the Color table has the packed RGB value at 4
+                unsafe { color.get::<i32>(4, Some(-1)).unwrap() }
+            })
+            .collect();
+        assert_eq!(colors, [0x000000, 0xff0000]);
+    }
+
+    #[tokio::test]
+    async fn hyperion_image_mode_sends_one_row() {
+        let colors = gradient(3);
+        let messages = run_hyperion(HyperionMode::Image, &[&colors]).await;
+        let commands = commands(&messages);
+        let [(command_type, image)] = commands.as_slice() else {
+            panic!("expected one image, got {} commands", commands.len());
+        };
+        assert_eq!(*command_type, HYPERION_IMAGE);
+        // SAFETY: the Image table has the union type at 4 and a RawImage at 6,
+        // which has the data at 4, the width at 6 and the height at 8
+        unsafe {
+            assert_eq!(image.get::<u8>(4, Some(0)), Some(HYPERION_RAW_IMAGE));
+            let raw = image.get::<ForwardsUOffset<Table>>(6, None).unwrap();
+            let data = raw
+                .get::<ForwardsUOffset<flatbuffers::Vector<u8>>>(4, None)
+                .unwrap();
+            assert_eq!(data.bytes(), colors.concat());
+            assert_eq!(raw.get::<i32>(6, Some(-1)), Some(3));
+            assert_eq!(raw.get::<i32>(8, Some(-1)), Some(1));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use opencv::{
+    core,
+    prelude::*,
+    videoio::{self, VideoCapture},
+};
+
+/// Captured 8-bit BGR image, handed from the source to the samplers without copying
+#[derive(Debug)]
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    /// Continuous `CV_8UC3` image
+    pub mat: Mat,
+    /// Capture time in milliseconds, `None` if the source doesn't report one
+    pub timestamp_ms: Option<f64>,
+}
+
+impl Frame {
+    /// Wraps `mat`, which is only copied if its rows aren't stored back to back
+    pub fn new(mat: Mat, timestamp_ms: Option<f64>) -> Result<Self, Box<dyn std::error::Error>> {
+        if mat.typ() != core::CV_8UC3 {
+            return Err(format!("Unsupported frame type {}", mat.typ()).into());
+        }
+        let mat = if mat.is_continuous() {
+            mat
+        } else {
+            mat.try_clone()?
+        };
+        Ok(Self {
+            width: mat.cols(),
+            height: mat.rows(),
+            mat,
+            timestamp_ms,
+        })
+    }
+
+    /// Pixels of the frame, for reading many of them one by one
+    pub fn pixels(&self) -> Pixels<'_> {
+        Pixels {
+            data: self.mat.data_bytes().unwrap_or_default(),
+            width: self.width,
+        }
+    }
+}
+
+/// Borrowed pixel data of a [`Frame`]
+pub struct Pixels<'a> {
+    data: &'a [u8],
+    width: i32,
+}
+
+impl Pixels<'_> {
+    /// Returns the pixel at (`x`, `y`) as `[r, g, b]`
+    pub fn rgb_at(&self, x: i32, y: i32) -> [u8; 3] {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        [self.data[i + 2], self.data[i + 1], self.data[i]]
+    }
+}
+
+/// Anything that can deliver screen frames to the region sampler
+pub trait FrameSource {
+    /// Grabs the next frame. `Ok(None)` means the source had nothing to deliver this time.
+    fn next_frame(&mut self) -> Result<Option<Frame>, Box<dyn std::error::Error>>;
+
+    /// Closes the source and opens it again after it stopped delivering frames
+    fn reopen(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// V4L2 device read through OpenCV (v4l2loopback fed by wf-recorder or ffmpeg)
+pub struct V4l2Source {
+    cam: i32,
+    /// `None` while the device can't be opened
+    cap: Option<VideoCapture>,
+    img: Mat,
+}
+
+impl V4l2Source {
+    /// Opens camera `cam`, a missing device delivers no frames until reopened
+    pub fn open(cam: i32) -> Self {
+        let mut source = Self {
+            cam,
+            cap: None,
+            img: Mat::default(),
+        };
+        if let Err(e) = source.reopen() {
+            eprintln!("Can't open camera {cam}: {e}");
+        }
+        source
+    }
+}
+
+impl FrameSource for V4l2Source {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Box<dyn std::error::Error>> {
+        let Some(cap) = &mut self.cap else {
+            return Ok(None);
+        };
+        if !cap.read(&mut self.img)? || self.img.empty() {
+            return Ok(None);
+        }
+        // Drivers that don't report capture times return 0
+        let timestamp_ms = Some(cap.get(videoio::CAP_PROP_POS_MSEC)?).filter(|&t| t > 0.0);
+        // The frame takes the buffer, the capture decodes the next one into a new Mat
+        let img = std::mem::take(&mut self.img);
+        Frame::new(img, timestamp_ms).map(Some)
+    }
+
+    fn reopen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Release the old capture before opening the device again
+        self.cap = None;
+        let cap = VideoCapture::new(self.cam, videoio::CAP_V4L2)?;
+        if !cap.is_opened()? {
+            return Err(format!("/dev/video{} is not available", self.cam).into());
+        }
+        self.cap = Some(cap);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `width` x `height` frame with `rgb(x, y)` at each pixel
+    pub(crate) fn frame(width: i32, height: i32, rgb: impl Fn(i32, i32) -> [u8; 3]) -> Frame {
+        let mut mat =
+            Mat::new_rows_cols_with_default(height, width, core::CV_8UC3, core::Scalar::all(0.0))
+                .unwrap();
+        let data = mat.data_bytes_mut().unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b] = rgb(x, y);
+                let i = (y * width + x) as usize * 3;
+                data[i..i + 3].copy_from_slice(&[b, g, r]);
+            }
+        }
+        Frame::new(mat, None).unwrap()
+    }
+}